@@ -1,4 +1,260 @@
 <?xml version="1.0" encoding="UTF-8"?>
 <tileset version="1.10" tiledversion="1.11.2" name="tileset" tilewidth="8" tileheight="8" tilecount="256" columns="16">
  <image source="tileset.ase" width="128" height="128"/>
+ <tile id="14">
+  <properties>
+   <property name="marker" value="spawn"/>
+  </properties>
+ </tile>
+ <tile id="15">
+  <properties>
+   <property name="marker" value="finish"/>
+  </properties>
+ </tile>
+ <tile id="16">
+  <properties>
+   <property name="collision" value="death"/>
+  </properties>
+ </tile>
+ <tile id="17">
+  <properties>
+   <property name="collision" value="death"/>
+  </properties>
+ </tile>
+ <tile id="18">
+  <properties>
+   <property name="collision" value="death"/>
+  </properties>
+ </tile>
+ <tile id="19">
+  <properties>
+   <property name="collision" value="death"/>
+  </properties>
+ </tile>
+ <tile id="20">
+  <properties>
+   <property name="collision" value="death"/>
+  </properties>
+ </tile>
+ <tile id="21">
+  <properties>
+   <property name="collision" value="death"/>
+  </properties>
+ </tile>
+ <tile id="22">
+  <properties>
+   <property name="collision" value="death"/>
+  </properties>
+ </tile>
+ <tile id="23">
+  <properties>
+   <property name="collision" value="death"/>
+  </properties>
+ </tile>
+ <tile id="24">
+  <properties>
+   <property name="collision" value="death"/>
+  </properties>
+ </tile>
+ <tile id="25">
+  <properties>
+   <property name="collision" value="death"/>
+  </properties>
+ </tile>
+ <tile id="26">
+  <properties>
+   <property name="collision" value="death"/>
+  </properties>
+ </tile>
+ <tile id="27">
+  <properties>
+   <property name="collision" value="death"/>
+  </properties>
+ </tile>
+ <tile id="28">
+  <properties>
+   <property name="collision" value="death"/>
+  </properties>
+ </tile>
+ <tile id="29">
+  <properties>
+   <property name="collision" value="death"/>
+  </properties>
+ </tile>
+ <tile id="30">
+  <properties>
+   <property name="collision" value="death"/>
+  </properties>
+ </tile>
+ <tile id="31">
+  <properties>
+   <property name="collision" value="death"/>
+  </properties>
+ </tile>
+ <tile id="32">
+  <properties>
+   <property name="collision" value="oneway"/>
+  </properties>
+ </tile>
+ <tile id="33">
+  <properties>
+   <property name="collision" value="oneway"/>
+  </properties>
+ </tile>
+ <tile id="34">
+  <properties>
+   <property name="collision" value="oneway"/>
+  </properties>
+ </tile>
+ <tile id="35">
+  <properties>
+   <property name="collision" value="oneway"/>
+  </properties>
+ </tile>
+ <tile id="36">
+  <properties>
+   <property name="collision" value="oneway"/>
+  </properties>
+ </tile>
+ <tile id="37">
+  <properties>
+   <property name="collision" value="oneway"/>
+  </properties>
+ </tile>
+ <tile id="38">
+  <properties>
+   <property name="collision" value="oneway"/>
+  </properties>
+ </tile>
+ <tile id="39">
+  <properties>
+   <property name="collision" value="oneway"/>
+  </properties>
+ </tile>
+ <tile id="40">
+  <properties>
+   <property name="collision" value="oneway"/>
+  </properties>
+ </tile>
+ <tile id="41">
+  <properties>
+   <property name="collision" value="oneway"/>
+  </properties>
+ </tile>
+ <tile id="42">
+  <properties>
+   <property name="collision" value="oneway"/>
+  </properties>
+ </tile>
+ <tile id="43">
+  <properties>
+   <property name="collision" value="oneway"/>
+  </properties>
+ </tile>
+ <tile id="44">
+  <properties>
+   <property name="collision" value="oneway"/>
+  </properties>
+ </tile>
+ <tile id="45">
+  <properties>
+   <property name="collision" value="oneway"/>
+  </properties>
+ </tile>
+ <tile id="46">
+  <properties>
+   <property name="collision" value="oneway"/>
+  </properties>
+ </tile>
+ <tile id="47">
+  <properties>
+   <property name="collision" value="oneway"/>
+  </properties>
+ </tile>
+ <tile id="48">
+  <properties>
+   <property name="collision" value="solid"/>
+   <property name="breakable" type="bool" value="true"/>
+  </properties>
+ </tile>
+ <tile id="49">
+  <properties>
+   <property name="collision" value="solid"/>
+  </properties>
+ </tile>
+ <tile id="50">
+  <properties>
+   <property name="collision" value="solid"/>
+  </properties>
+ </tile>
+ <tile id="51">
+  <properties>
+   <property name="collision" value="solid"/>
+  </properties>
+ </tile>
+ <tile id="52">
+  <properties>
+   <property name="collision" value="solid"/>
+  </properties>
+ </tile>
+ <tile id="53">
+  <properties>
+   <property name="collision" value="solid"/>
+  </properties>
+ </tile>
+ <tile id="54">
+  <properties>
+   <property name="collision" value="solid"/>
+  </properties>
+ </tile>
+ <tile id="55">
+  <properties>
+   <property name="collision" value="solid"/>
+  </properties>
+ </tile>
+ <tile id="56">
+  <properties>
+   <property name="collision" value="solid"/>
+  </properties>
+ </tile>
+ <tile id="57">
+  <properties>
+   <property name="collision" value="solid"/>
+  </properties>
+ </tile>
+ <tile id="58">
+  <properties>
+   <property name="collision" value="solid"/>
+  </properties>
+ </tile>
+ <tile id="59">
+  <properties>
+   <property name="collision" value="solid"/>
+  </properties>
+ </tile>
+ <tile id="60">
+  <properties>
+   <property name="collision" value="solid"/>
+  </properties>
+ </tile>
+ <tile id="61">
+  <properties>
+   <property name="collision" value="solid"/>
+  </properties>
+ </tile>
+ <tile id="62">
+  <properties>
+   <property name="collision" value="solid"/>
+  </properties>
+ </tile>
+ <tile id="63">
+  <properties>
+   <property name="collision" value="solid"/>
+  </properties>
+ </tile>
+ <tile id="112">
+  <properties>
+   <property name="bounce" type="float" value="4.5"/>
+  </properties>
+ </tile>
 </tileset>
@@ -0,0 +1,58 @@
+//! Compiles the levels listed in `assets/levels/levels.xml` into the binary format the game
+//! embeds, checking them the way the game does when it loads them, so broken levels fail the
+//! build instead of panicking when the game starts.
+
+use std::{env, fs, path::Path};
+
+#[allow(dead_code)]
+#[path = "src/levels.rs"]
+mod levels;
+#[allow(dead_code)]
+#[path = "src/tmx.rs"]
+mod tmx;
+
+use levels::{LevelData, MUSIC_TRACKS, TileMarker};
+use tmx::TmxError;
+
+fn main() {
+    println!("cargo::rerun-if-changed=assets/levels");
+    println!("cargo::rerun-if-changed=assets/tileset.tsx");
+    println!("cargo::rerun-if-changed=src/tmx.rs");
+    println!("cargo::rerun-if-changed=src/levels.rs");
+
+    // the game reads spawn and finish markers from the tileset, so they have to be valid
+    let xml = fs::read_to_string("assets/tileset.tsx").unwrap();
+    let tileset = tmx::parse_tileset("tileset.tsx", &xml).unwrap_or_else(|err| panic!("{err}"));
+    for tile in tileset.tiles.iter() {
+        if let Err(kind) = TileMarker::from_properties(&tile.properties) {
+            panic!("{}", TmxError::new(&tileset.file, None, tile.line, kind));
+        }
+    }
+
+    // only the levels the manifest lists are embedded
+    let xml = fs::read_to_string("assets/levels/levels.xml").unwrap();
+    let manifest = levels::parse_manifest("levels.xml", &xml).unwrap_or_else(|err| panic!("{err}"));
+    let mut maps = Vec::new();
+    for info in manifest {
+        let path = Path::new("assets/levels").join(&info.file);
+        let Ok(xml) = fs::read_to_string(&path) else {
+            panic!("levels.xml lists {}, which is missing", info.file);
+        };
+        let mut map =
+            tmx::parse_map(&path.to_string_lossy(), &xml).unwrap_or_else(|err| panic!("{err}"));
+        // levels are looked up by their path relative to the levels directory
+        map.file = info.file.clone();
+        let data = LevelData::from_map(&map).unwrap_or_else(|err| panic!("{err}"));
+        // the manifest's music overrides the level's own
+        let music = info.music.as_ref().unwrap_or(&data.settings.music);
+        assert!(
+            MUSIC_TRACKS.contains(&music.as_str()),
+            "{} uses music \"{music}\", which doesn't exist",
+            info.file
+        );
+        maps.push(map);
+    }
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("levels.bin");
+    fs::write(out, tmx::encode_maps(&maps)).unwrap();
+}
@@ -1,8 +1,7 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, f32::consts::PI};
 
 use asefile::AsepriteFile;
 use image::EncodableLayout;
-use include_dir::{Dir, include_dir};
 use macroquad::{
     audio::{Sound, load_sound_from_bytes},
     prelude::*,
@@ -10,15 +9,33 @@ use macroquad::{
 use num_traits::FromPrimitive;
 
 use crate::{
-    enemy::{Enemy, EnemyType},
-    physics::get_tile_flag,
+    enemy::Enemy,
+    levels::{
+        EnemyType, LevelData, LevelInfo, LevelObject, LevelSettings, MUSIC_TRACKS, TileMarker,
+        parse_manifest, read_pack_dir,
+    },
+    physics::TileBehaviours,
     player::Player,
+    tmx::{
+        FLIPPED_DIAGONALLY, FLIPPED_HORIZONTALLY, FLIPPED_VERTICALLY, Map, TileLayer, Tileset,
+        TmxError, TmxErrorKind, decode_maps, parse_map, parse_tileset, tile_id,
+    },
+    utils::{SCREEN_HEIGHT, SCREEN_WIDTH},
 };
+pub const BUILTIN_TILESET: &str = include_str!("../assets/tileset.tsx");
+/// The built in levels, compiled from `assets/levels` by build.rs
+pub static BUILTIN_LEVELS: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/levels.bin"));
+pub const BUILTIN_MANIFEST: &str = include_str!("../assets/levels/levels.xml");
+
 pub struct Assets {
     pub player: AnimationsGroup,
     pub enemies: AnimationsGroup,
     pub tileset: Spritesheet,
     pub levels: Vec<World>,
+    /// Names of the level packs, the built in campaign being the first
+    pub packs: Vec<String>,
+    pub tiles: TileBehaviours,
+    pub tile_animations: TileAnimations,
     pub coin: Animation,
     pub boat: Texture2D,
 
@@ -33,18 +50,42 @@ pub struct Assets {
     pub coin_sfx: Sound,
     pub stomp_sfx: Sound,
     pub jump_sfx: Sound,
-    pub song: Sound,
+    pub music: HashMap<String, Sound>,
 }
 impl Assets {
     pub async fn load() -> Self {
+        let tileset =
+            parse_tileset("tileset.tsx", BUILTIN_TILESET).unwrap_or_else(|err| panic!("{err}"));
+        let tiles = TileBehaviours::from_tileset(&tileset).unwrap_or_else(|err| panic!("{err}"));
+        let tile_animations =
+            TileAnimations::from_tileset(&tileset).unwrap_or_else(|err| panic!("{err}"));
+        let mut music = HashMap::new();
+        music.insert(
+            "song".to_string(),
+            load_sound_from_bytes(include_bytes!("../assets/sfx/song.wav"))
+                .await
+                .unwrap(),
+        );
+        // build.rs only lets levels use these, so they all have to be loaded
+        debug_assert!(MUSIC_TRACKS.iter().all(|name| music.contains_key(*name)));
+
+        let maps = decode_maps("levels.bin", BUILTIN_LEVELS).unwrap_or_else(|err| panic!("{err}"));
+        let manifest =
+            parse_manifest("levels.xml", BUILTIN_MANIFEST).unwrap_or_else(|err| panic!("{err}"));
         let mut levels = Vec::new();
-        static LEVELS_DIR: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/assets/levels");
-        let mut iter = LEVELS_DIR.files().peekable();
-        while let Some(file) = iter.next() {
-            if iter.peek().is_none() {
-                continue;
-            }
-            let level = World::from_data(file.contents_utf8().unwrap());
+        for info in manifest {
+            let map = maps
+                .iter()
+                .find(|map| map.file == info.file)
+                .unwrap_or_else(|| panic!("levels.xml lists {}, which is missing", info.file));
+            let mut level = World::from_map(map, &tiles).unwrap_or_else(|err| panic!("{err}"));
+            level.set_info(info);
+            assert!(
+                music.contains_key(&level.settings.music),
+                "{} uses music \"{}\", which doesn't exist",
+                level.info.file,
+                level.settings.music
+            );
             levels.push(level);
         }
         Self {
@@ -77,11 +118,55 @@ impl Assets {
             jump_sfx: load_sound_from_bytes(include_bytes!("../assets/sfx/jump.wav"))
                 .await
                 .unwrap(),
-            song: load_sound_from_bytes(include_bytes!("../assets/sfx/song.wav"))
-                .await
-                .unwrap(),
+            music,
 
             levels,
+            packs: vec!["gnobbler".to_string()],
+            tiles,
+            tile_animations,
+        }
+    }
+    /// Loads the levels in a directory as an extra level pack. Levels that fail to load are skipped.
+    pub fn load_level_pack(&mut self, dir: &std::path::Path) {
+        let pack = self.packs.len();
+        let infos = match read_pack_dir(dir) {
+            Ok(infos) => infos,
+            Err(err) => {
+                warn!("couldn't load level pack {}: {err}", dir.display());
+                return;
+            }
+        };
+        let mut loaded = 0;
+        for mut info in infos {
+            let path = dir.join(&info.file);
+            let name = path.to_string_lossy();
+            let level = std::fs::read_to_string(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|xml| {
+                    World::from_data(&name, &xml, &self.tiles).map_err(|err| err.to_string())
+                });
+            let mut level = match level {
+                Ok(level) => level,
+                Err(err) => {
+                    warn!("skipping {name}: {err}");
+                    continue;
+                }
+            };
+            info.pack = pack;
+            level.set_info(info);
+            if !self.music.contains_key(&level.settings.music) {
+                warn!(
+                    "{name} uses music \"{}\", which doesn't exist",
+                    level.settings.music
+                );
+                level.settings.music = LevelSettings::default().music;
+            }
+            self.levels.push(level);
+            loaded += 1;
+        }
+        if loaded > 0 {
+            let name = dir.file_name().unwrap_or(dir.as_os_str());
+            self.packs.push(name.to_string_lossy().to_string());
         }
     }
     pub fn draw_number(&self, text: &str, x: f32, y: f32) {
@@ -89,7 +174,7 @@ impl Assets {
             let i = char as u32 - '0' as u32;
             assert!(i <= 9);
             self.numbers
-                .draw_tile(x + index as f32 * 5.0, y, i as f32, 0.0, None);
+                .draw_tile(x + index as f32 * 5.0, y, i as f32, 0.0, None, WHITE);
         }
     }
 }
@@ -135,6 +220,63 @@ impl Animation {
     }
 }
 
+/// Frames of the animated tiles in the tileset, as tile ids and durations in milliseconds.
+#[derive(Clone, Default)]
+pub struct TileAnimations(HashMap<u32, (Vec<(u32, u32)>, u32)>);
+impl TileAnimations {
+    pub fn from_tileset(tileset: &Tileset) -> Result<Self, TmxError> {
+        let mut animations = HashMap::new();
+        for tile in tileset
+            .tiles
+            .iter()
+            .filter(|tile| !tile.animation.is_empty())
+        {
+            let error = |kind| TmxError::new(&tileset.file, None, tile.line, kind);
+            let mut frames = Vec::new();
+            let mut total_length = 0;
+            for frame in tile.animation.iter() {
+                if frame.tile_id >= tileset.tile_count {
+                    return Err(error(TmxErrorKind::InvalidAttribute(
+                        "tileid",
+                        frame.tile_id.to_string(),
+                    )));
+                }
+                frames.push((frame.tile_id, frame.duration));
+                total_length += frame.duration;
+            }
+            if total_length == 0 {
+                return Err(error(TmxErrorKind::InvalidAttribute(
+                    "duration",
+                    "0".to_string(),
+                )));
+            }
+            animations.insert(tile.id, (frames, total_length));
+        }
+        Ok(Self(animations))
+    }
+    pub fn is_animated(&self, gid: u32) -> bool {
+        tile_id(gid)
+            .checked_sub(1)
+            .is_some_and(|id| self.0.contains_key(&id))
+    }
+    /// Gets the gid to draw in place of a gid at a time in milliseconds, keeping its flip flags
+    pub fn frame(&self, gid: u32, mut time: u32) -> u32 {
+        let id = tile_id(gid);
+        let Some((frames, total_length)) = id.checked_sub(1).and_then(|id| self.0.get(&id)) else {
+            return gid;
+        };
+        time %= total_length;
+        for (frame, length) in frames.iter() {
+            if time >= *length {
+                time -= length;
+            } else {
+                return gid - id + frame + 1;
+            }
+        }
+        gid
+    }
+}
+
 pub struct AnimationsGroup {
     #[expect(dead_code)]
     pub file: AsepriteFile,
@@ -191,7 +333,7 @@ impl AnimationsGroup {
         }
     }
 }
-fn load_ase_texture(bytes: &[u8], layer: Option<u32>) -> Texture2D {
+pub fn load_ase_texture(bytes: &[u8], layer: Option<u32>) -> Texture2D {
     let img = AsepriteFile::read(bytes).unwrap();
     let img = if let Some(layer) = layer {
         img.layer(layer).frame(0).image()
@@ -235,6 +377,43 @@ impl Spritesheet {
             tile_x,
             tile_y,
             params,
+            WHITE,
+        );
+    }
+    /// Draws a tile from a level layer, flipped and rotated according to the flags of its gid
+    pub fn draw_gid(&self, screen_x: f32, screen_y: f32, gid: u32, color: Color) {
+        let (flip_x, flip_y) = (
+            gid & FLIPPED_HORIZONTALLY != 0,
+            gid & FLIPPED_VERTICALLY != 0,
+        );
+        // tiled applies the diagonal flip (swapping x and y) before the other flips.
+        // a diagonal flip is a quarter turn followed by a horizontal flip, so the
+        // combined transform can be expressed as a flip followed by a rotation.
+        let params = if gid & FLIPPED_DIAGONALLY != 0 {
+            DrawTextureParams {
+                flip_x: flip_x == flip_y,
+                rotation: if flip_x { PI / 2.0 } else { -PI / 2.0 },
+                ..Default::default()
+            }
+        } else {
+            DrawTextureParams {
+                flip_x,
+                flip_y,
+                ..Default::default()
+            }
+        };
+        // a gid with only flip flags set has no tile to draw
+        let Some(index) = tile_id(gid).checked_sub(1) else {
+            return;
+        };
+        let columns = (self.texture.width() / self.sprite_size) as u32;
+        self.draw_tile(
+            screen_x,
+            screen_y,
+            (index % columns) as f32,
+            (index / columns) as f32,
+            Some(&params),
+            color,
         );
     }
     /// Draws a single tile from the spritesheet
@@ -245,6 +424,7 @@ impl Spritesheet {
         tile_x: f32,
         tile_y: f32,
         params: Option<&DrawTextureParams>,
+        color: Color,
     ) {
         let mut p = params.cloned().unwrap_or(DrawTextureParams::default());
         p.dest_size = p
@@ -256,228 +436,480 @@ impl Spritesheet {
             w: self.sprite_size,
             h: self.sprite_size,
         }));
-        draw_texture_ex(&self.texture, screen_x, screen_y, WHITE, p);
+        draw_texture_ex(&self.texture, screen_x, screen_y, color, p);
     }
 }
 
 #[derive(Default, Clone)]
 pub struct WorldState {
     pub enemies: Vec<Enemy>,
-    pub broken_tiles: Vec<(i16, i16)>,
-    pub coins: Vec<(i16, i16)>,
+    /// Tiles of the collision layer changed during play, like broken blocks
+    pub tile_overrides: TileOverrides,
+    pub coins: Vec<Vec2>,
     pub boat_offset: f32,
+    /// The boat's offset before the last step
+    pub prev_boat_offset: f32,
+}
+/// A tile layer that is drawn, but doesn't affect gameplay unless it's the collision layer.
+pub struct DrawLayer {
+    pub tiles: TileGrid,
+    pub parallax: Vec2,
+    pub offset: Vec2,
+    pub opacity: f32,
+}
+impl DrawLayer {
+    fn new(tiles: TileGrid) -> Self {
+        Self {
+            tiles,
+            parallax: Vec2::ONE,
+            offset: Vec2::ZERO,
+            opacity: 1.0,
+        }
+    }
+    /// Where the layer's origin is drawn when the camera is centered on `camera`
+    pub fn origin(&self, camera: Vec2, parallax_origin: Vec2) -> Vec2 {
+        self.offset + (camera - parallax_origin) * (Vec2::ONE - self.parallax)
+    }
 }
 pub struct World {
-    pub collision: HashMap<(i16, i16), Chunk>,
-    pub details: HashMap<(i16, i16), Chunk>,
-    pub background: HashMap<(i16, i16), Chunk>,
-    pub special: HashMap<(i16, i16), Chunk>,
+    /// Every drawn layer, back to front
+    pub layers: Vec<DrawLayer>,
+    collision_layer: usize,
+    pub special: TileGrid,
+    pub parallax_origin: Vec2,
 
-    pub finish_pos: (i16, i16),
+    /// The last ground tile of the level, which the dock is built to the right of.
+    /// Levels with a finish marker or object end there, without a dock.
+    pub finish_pos: Option<(i16, i16)>,
+    /// Reaching this area finishes the level
+    pub finish_area: Rect,
+    /// The player can't leave this area except by falling out of the bottom, which kills them
+    pub bounds: Rect,
+    pub tiles: TileBehaviours,
+    pub info: LevelInfo,
+    pub settings: LevelSettings,
 
+    spawn: Option<Vec2>,
     world_state: WorldState,
 }
 impl World {
+    pub fn collision(&self) -> &TileGrid {
+        &self.layers[self.collision_layer].tiles
+    }
+    /// Index of the collision layer in `layers`
+    pub fn collision_layer(&self) -> usize {
+        self.collision_layer
+    }
     pub fn load_level(&self) -> (WorldState, Player) {
-        (
-            self.world_state.clone(),
-            Player::new(self.get_player_spawn()),
-        )
+        (self.world_state.clone(), {
+            let mut player = Player::new(self.get_player_spawn());
+            player.camera_pos = self.clamp_camera(player.camera_pos);
+            player.prev_camera_pos = player.camera_pos;
+            player
+        })
     }
-    fn get_player_spawn(&self) -> Vec2 {
-        let mut highest = i16::MAX;
-        for chunk in self.collision.values().filter(|f| f.x == 0) {
-            for row in 0..16 {
-                let tile = chunk.tiles[row * 16];
-                if tile != 0 {
-                    let y = chunk.y + row as i16;
-                    if y < highest {
-                        highest = y;
-                    }
-                }
+    /// Sets the level's manifest entry, which can override the level's music.
+    pub fn set_info(&mut self, info: LevelInfo) {
+        if let Some(music) = &info.music {
+            self.settings.music = music.clone();
+        }
+        self.info = info;
+    }
+    /// Moves a camera target so the camera doesn't show anything outside the level bounds.
+    /// Levels smaller than the screen are centered instead.
+    pub fn clamp_camera(&self, target: Vec2) -> Vec2 {
+        let half_screen = vec2(SCREEN_WIDTH, SCREEN_HEIGHT) / 2.0;
+        let min = self.bounds.point() + half_screen;
+        let max = self.bounds.point() + self.bounds.size() - half_screen;
+        let clamp = |value: f32, min: f32, max: f32| {
+            if min > max {
+                (min + max) / 2.0
+            } else {
+                value.clamp(min, max)
             }
+        };
+        vec2(clamp(target.x, min.x, max.x), clamp(target.y, min.y, max.y))
+    }
+    fn get_player_spawn(&self) -> Vec2 {
+        if let Some(spawn) = self.spawn {
+            return spawn;
         }
+        let collision = self.collision();
+        let highest = (collision.y..collision.y + collision.height as i16)
+            .find(|y| collision.get(0, *y) != 0)
+            .unwrap_or(i16::MAX);
         vec2(1.0, (highest - 1) as f32 * 8.0)
     }
     #[expect(dead_code)]
-    pub fn get_interactable_spawn(&self, tile_index: i16) -> Option<Vec2> {
-        for chunk in self.special.values() {
-            for (i, tile) in chunk.tiles.iter().enumerate() {
-                if *tile == tile_index + 1 {
-                    return Some(Vec2::new(
-                        (i as i16 % 16 + chunk.x) as f32 * 8.0,
-                        (i as i16 / 16 + chunk.y) as f32 * 8.0,
-                    ));
-                }
+    pub fn get_interactable_spawn(&self, tile_index: u32) -> Option<Vec2> {
+        self.special
+            .iter()
+            .find(|(_, _, tile)| tile_id(*tile) == tile_index + 1)
+            .map(|(x, y, _)| vec2(x as f32 * 8.0, y as f32 * 8.0))
+    }
+    pub fn from_data(file: &str, xml: &str, tiles: &TileBehaviours) -> Result<Self, TmxError> {
+        Self::from_map(&parse_map(file, xml)?, tiles)
+    }
+    pub fn from_map(map: &Map, tiles: &TileBehaviours) -> Result<Self, TmxError> {
+        let LevelData { settings, objects } = LevelData::from_map(map)?;
+        let special = load_grid(map.layer("special"));
+        // every other layer is drawn, so levels can add as many layers of scenery as they want
+        let mut layers = Vec::new();
+        let mut collision_layer = 0;
+        for layer in map.layers.iter().filter(|layer| layer.name != "special") {
+            if layer.name == "collision" {
+                collision_layer = layers.len();
             }
+            layers.push(DrawLayer {
+                parallax: vec2(layer.parallax.0, layer.parallax.1),
+                offset: vec2(layer.offset.0, layer.offset.1),
+                opacity: layer.opacity,
+                ..DrawLayer::new(load_grid(Some(layer)))
+            });
         }
-        None
-    }
-    pub fn from_data(xml: &str) -> Self {
-        let collision = get_layer(xml, "collision");
-        let detail = get_layer(xml, "detail");
-        let special = get_layer(xml, "special");
-        let background = get_layer(xml, "background");
+        let collision = &layers[collision_layer].tiles;
 
         let mut world_state = WorldState::default();
-        let special = get_all_chunks(special);
-        for chunk in special.values() {
-            for (index, tile) in chunk.tiles.iter().enumerate() {
-                let x = (index % 16) as i16 + chunk.x;
-                let y = (index / 16) as i16 + chunk.y;
-                if *tile == 0 {
-                    continue;
-                } else if *tile == 1 {
-                    world_state.coins.push((x, y));
+        let mut spawn = None;
+        let mut finish = None;
+        for (x, y, tile) in special.iter() {
+            let tile = tile_id(tile);
+            match tiles.get(tile).marker {
+                Some(TileMarker::Spawn) => {
+                    spawn = Some(vec2(x as f32 * 8.0, y as f32 * 8.0));
                     continue;
                 }
-
-                let tile = *tile - 2;
-                if tile >= 16 {
+                Some(TileMarker::Finish) => {
+                    finish = Some(Rect::new(x as f32 * 8.0, y as f32 * 8.0, 8.0, 8.0));
                     continue;
                 }
-                let Some(ty) = EnemyType::from_i16(tile) else {
-                    warn!("ty {tile} doesnt exist!");
-                    continue;
-                };
-                let enemy = Enemy::new(vec2(x as f32 * 8.0, y as f32 * 8.0), ty);
-                world_state.enemies.push(enemy);
+                None => {}
+            }
+            if tile == 1 {
+                world_state.coins.push(vec2(x as f32 * 8.0, y as f32 * 8.0));
+                continue;
+            }
+
+            let tile = tile - 2;
+            if tile >= 16 {
+                continue;
             }
+            // --check-levels reports enemy tiles without an enemy type
+            let Some(ty) = EnemyType::from_u32(tile) else {
+                continue;
+            };
+            let enemy = Enemy::new(vec2(x as f32 * 8.0, y as f32 * 8.0), ty);
+            world_state.enemies.push(enemy);
         }
 
-        let mut finish_line_pos = (i16::MIN, i16::MIN, i16::MIN, i16::MIN);
-        let mut background = get_all_chunks(background);
-        let collision = get_all_chunks(collision);
-        for chunk in collision.values() {
-            if chunk.x >= finish_line_pos.0 {
-                let mut highest_x = i16::MIN;
-
-                'outer: for x in (0..16).rev() {
-                    for y in 0..16 {
-                        let tile = chunk.tile_at(x, y).unwrap();
-                        let flags = get_tile_flag(tile);
-                        if !flags.is_no_collision() && !flags.is_death() {
-                            highest_x = x as i16;
-                            break 'outer;
-                        }
+        for (x, y, object) in objects {
+            let pos = vec2(x, y);
+            match object {
+                LevelObject::Coin => world_state.coins.push(pos),
+                LevelObject::Enemy {
+                    ty,
+                    facing_left,
+                    speed,
+                } => {
+                    let mut enemy = Enemy::new(pos, ty);
+                    if let Some(facing_left) = facing_left {
+                        enemy.facing_left = facing_left;
                     }
-                }
-                if highest_x < 0 {
-                    continue;
-                }
-
-                let mut highest_y = i16::MIN;
-                for y in 0..16 {
-                    let tile = chunk.tile_at(highest_x as usize, y).unwrap();
-                    let flags = get_tile_flag(tile);
-                    if !flags.is_no_collision() && !flags.is_death() {
-                        highest_y = y as i16;
-                        break;
+                    if let Some(speed) = speed {
+                        enemy.speed = speed;
                     }
+                    world_state.enemies.push(enemy);
                 }
-                if highest_x + chunk.x > finish_line_pos.2 {
-                    finish_line_pos = (chunk.x, chunk.y, chunk.x + highest_x, highest_y + chunk.y);
+                LevelObject::Spawn => spawn = Some(pos),
+                LevelObject::Finish { width, height } => {
+                    finish = Some(Rect::new(x, y, width, height));
                 }
             }
         }
-        let mut new = Chunk {
-            x: finish_line_pos.0,
-            y: finish_line_pos.1,
-            tiles: vec![97; 16 * 16],
+
+        // without a finish marker, the level ends at the right-most solid column
+        let (finish_pos, finish_area) = if let Some(finish) = finish {
+            (None, finish)
+        } else {
+            let solid = |(_, _, tile): &(i16, i16, u32)| {
+                let flags = tiles.get(*tile).flag;
+                !flags.is_no_collision() && !flags.is_death()
+            };
+            let last_x = collision
+                .iter()
+                .filter(solid)
+                .map(|(x, _, _)| x)
+                .max()
+                .unwrap_or(i16::MIN);
+            let finish_pos = collision
+                .iter()
+                .filter(solid)
+                .find(|(x, _, _)| *x == last_x)
+                .map_or((i16::MIN, i16::MIN), |(x, y, _)| (x, y));
+            // everything right of the last column finishes the level, once the player is fully on it
+            let area = Rect::new(
+                finish_pos.0 as f32 * 8.0 + 8.0,
+                -1.0e6,
+                f32::INFINITY,
+                f32::INFINITY,
+            );
+            (Some(finish_pos), area)
         };
-        for x in 0..16 {
-            new.tiles[x] = 0;
-        }
-        for i in 16..32 {
-            new.tiles[i as usize] = 19;
+
+        // the dock the boat waits at, drawn just behind the collision layer
+        let dock = finish_pos.map(|(finish_x, finish_y)| {
+            let rect = Rect::new(
+                finish_x as f32 * 8.0,
+                finish_y as f32 * 8.0,
+                32.0 * 8.0,
+                16.0 * 8.0,
+            );
+            let tiles = TileGrid::from_tiles((0..32).flat_map(|x| {
+                (1..16).map(move |y| {
+                    let tile = if y == 1 { 19 } else { 97 };
+                    (finish_x + x, finish_y + y, tile)
+                })
+            }));
+            (rect, tiles)
+        });
+        let dock_rect = dock.as_ref().map(|(rect, _)| *rect);
+        if let Some((_, tiles)) = dock {
+            layers.insert(collision_layer, DrawLayer::new(tiles));
+            collision_layer += 1;
         }
-        background.insert((finish_line_pos.2, finish_line_pos.3), new.clone());
-        background.insert((finish_line_pos.2 + 16, finish_line_pos.3), new);
 
-        World {
-            collision,
+        // without bounds set, the level is bounded by the chunks of its playfield layers and the dock
+        let bounds = settings.bounds.map_or_else(
+            || {
+                map.layers
+                    .iter()
+                    .filter(|layer| layer.parallax == (1.0, 1.0) && layer.offset == (0.0, 0.0))
+                    .flat_map(|layer| layer.chunks.iter())
+                    .map(|chunk| {
+                        let height = chunk.tiles.len() as u32 / chunk.width.max(1);
+                        Rect::new(
+                            chunk.x as f32 * 8.0,
+                            chunk.y as f32 * 8.0,
+                            chunk.width as f32 * 8.0,
+                            height as f32 * 8.0,
+                        )
+                    })
+                    .chain(dock_rect)
+                    .reduce(|bounds, chunk| bounds.combine_with(chunk))
+                    .unwrap_or_default()
+            },
+            |[x, y, width, height]| Rect::new(x, y, width, height),
+        );
+
+        Ok(World {
+            layers,
+            collision_layer,
             special,
-            background,
-            details: get_all_chunks(detail),
-            finish_pos: (finish_line_pos.2, finish_line_pos.3),
+            parallax_origin: vec2(map.parallax_origin.0, map.parallax_origin.1),
+            finish_pos,
+            finish_area,
+            bounds,
+            tiles: tiles.clone(),
+            info: LevelInfo::from_file(&map.file),
+            settings,
+            spawn,
             world_state,
-        }
+        })
     }
 }
 
-#[derive(Clone)]
-pub struct Chunk {
+/// A layer's tiles, stored densely over the bounding box of its non-empty tiles.
+#[derive(Clone, Default)]
+pub struct TileGrid {
+    /// Position of the top left tile
     pub x: i16,
     pub y: i16,
-    pub tiles: Vec<i16>,
+    pub width: usize,
+    pub height: usize,
+    /// Raw gids, which keep Tiled's flip flags.
+    tiles: Vec<u32>,
 }
-impl Chunk {
-    pub fn tile_at(&self, x: usize, y: usize) -> Option<i16> {
-        if x > 16 || y > 16 {
-            return None;
+impl TileGrid {
+    /// Builds a grid just big enough to hold the given `(x, y, gid)` tiles.
+    pub fn from_tiles(tiles: impl IntoIterator<Item = (i16, i16, u32)>) -> Self {
+        let tiles: Vec<_> = tiles.into_iter().filter(|(_, _, gid)| *gid != 0).collect();
+        let Some(left) = tiles.iter().map(|(x, _, _)| *x).min() else {
+            return Self::default();
+        };
+        let top = tiles.iter().map(|(_, y, _)| *y).min().unwrap();
+        let right = tiles.iter().map(|(x, _, _)| *x).max().unwrap();
+        let bottom = tiles.iter().map(|(_, y, _)| *y).max().unwrap();
+        let mut grid = Self {
+            x: left,
+            y: top,
+            width: (right as i32 - left as i32) as usize + 1,
+            height: (bottom as i32 - top as i32) as usize + 1,
+            tiles: Vec::new(),
+        };
+        grid.tiles = vec![0; grid.width * grid.height];
+        for (x, y, gid) in tiles {
+            let index = grid.index(x, y).unwrap();
+            grid.tiles[index] = gid;
         }
-        self.tiles.get(x + y * 16).cloned()
+        grid
+    }
+    fn index(&self, x: i16, y: i16) -> Option<usize> {
+        let x = usize::try_from(x as i32 - self.x as i32).ok()?;
+        let y = usize::try_from(y as i32 - self.y as i32).ok()?;
+        (x < self.width && y < self.height).then_some(x + y * self.width)
+    }
+    /// The gid at a tile position, or 0 outside the grid
+    pub fn get(&self, x: i16, y: i16) -> u32 {
+        self.index(x, y).map_or(0, |index| self.tiles[index])
+    }
+    /// Every non-empty tile as `(x, y, gid)`, row by row
+    pub fn iter(&self) -> impl Iterator<Item = (i16, i16, u32)> + '_ {
+        self.tiles
+            .iter()
+            .enumerate()
+            .filter(|(_, gid)| **gid != 0)
+            .map(|(index, gid)| {
+                (
+                    self.x + (index % self.width) as i16,
+                    self.y + (index / self.width) as i16,
+                    *gid,
+                )
+            })
     }
 }
 
-fn get_all_chunks(xml: &str) -> HashMap<(i16, i16), Chunk> {
-    let mut chunks = HashMap::new();
-    let mut xml = xml.to_string();
-    while let Some((current, remains)) = xml.split_once("</chunk>") {
-        let new = parse_chunk(current);
-        chunks.insert((new.x, new.y), new);
-        xml = remains.to_string();
+/// Tiles placed over a grid at runtime. Overridden positions can hold any tile, including
+/// none, whatever the grid has there.
+#[derive(Clone, Default, PartialEq)]
+pub struct TileOverrides(HashMap<(i16, i16), u32>);
+impl TileOverrides {
+    /// Places a tile, replacing whatever was there
+    pub fn set(&mut self, x: i16, y: i16, gid: u32) {
+        self.0.insert((x, y), gid);
+    }
+    /// Empties a tile
+    pub fn clear(&mut self, x: i16, y: i16) {
+        self.set(x, y, 0);
+    }
+    /// Brings back the grid's own tile
+    #[expect(dead_code)]
+    pub fn reset(&mut self, x: i16, y: i16) {
+        self.0.remove(&(x, y));
+    }
+    /// The overriding gid at a position, if there is one
+    pub fn get(&self, x: i16, y: i16) -> Option<u32> {
+        self.0.get(&(x, y)).copied()
+    }
+    /// The gid at a position of `grid`, with the overrides applied
+    pub fn tile(&self, grid: &TileGrid, x: i16, y: i16) -> u32 {
+        self.get(x, y).unwrap_or_else(|| grid.get(x, y))
+    }
+    /// Every overridden position, as `(x, y, gid)`
+    pub fn iter(&self) -> impl Iterator<Item = (i16, i16, u32)> + '_ {
+        self.0.iter().map(|((x, y), gid)| (*x, *y, *gid))
     }
-
-    chunks
 }
 
-fn get_layer<'a>(xml: &'a str, layer: &str) -> &'a str {
-    let split = format!(" name=\"{layer}");
-    xml.split_once(&split)
-        .unwrap()
-        .1
-        .split_once(">")
-        .unwrap()
-        .1
-        .split_once("</layer>")
-        .unwrap()
-        .0
+/// Packs the chunks of a layer into one grid.
+/// Collects a layer's tiles into a grid. [`LevelData::from_map`] has already checked that
+/// every tile's position fits.
+fn load_grid(layer: Option<&TileLayer>) -> TileGrid {
+    let Some(layer) = layer else {
+        return TileGrid::default();
+    };
+    TileGrid::from_tiles(layer.chunks.iter().flat_map(|chunk| {
+        chunk.tiles.iter().enumerate().map(|(index, tile)| {
+            let x = chunk.x + (index as u32 % chunk.width) as i32;
+            let y = chunk.y + (index as u32 / chunk.width) as i32;
+            (x as i16, y as i16, *tile)
+        })
+    }))
 }
 
-fn parse_chunk(xml: &str) -> Chunk {
-    let (tag, data) = xml
-        .split_once("<chunk ")
-        .unwrap()
-        .1
-        .split_once(">")
-        .unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let x = tag
-        .split_once("x=\"")
-        .unwrap()
-        .1
-        .split_once("\"")
-        .unwrap()
-        .0
-        .parse()
-        .unwrap();
-    let y = tag
-        .split_once("y=\"")
-        .unwrap()
-        .1
-        .split_once("\"")
-        .unwrap()
-        .0
-        .parse()
+    /// Builds a level with a 4x4 floor and the given objects in an object layer
+    fn level(objects: &str) -> Result<World, TmxError> {
+        let tiles = parse_tileset("tileset.tsx", BUILTIN_TILESET)
+            .and_then(|tileset| TileBehaviours::from_tileset(&tileset))
+            .unwrap();
+        let xml = format!(
+            r#"<map width="4" height="4" tilewidth="8" tileheight="8" infinite="0">
+                <tileset firstgid="1" source="tileset.tsx"/>
+                <layer name="collision" width="4" height="4">
+                    <data encoding="csv">0,0,0,0,0,0,0,0,0,0,0,0,50,50,50,50</data>
+                </layer>
+                <objectgroup name="entities">{objects}</objectgroup>
+            </map>"#
+        );
+        World::from_data("test.tmx", &xml, &tiles)
+    }
+
+    #[test]
+    fn objects_place_coins_and_enemies() {
+        let world = level(
+            r#"<object id="1" type="coin" gid="2" x="8" y="24" width="8" height="8"/>
+            <object id="2" type="enemy" x="16" y="16">
+                <properties>
+                    <property name="kind" value="crab"/>
+                    <property name="facing" value="right"/>
+                    <property name="speed" type="float" value="12.5"/>
+                </properties>
+            </object>"#,
+        )
         .unwrap();
+        // tile objects are positioned by their bottom left corner
+        assert_eq!(world.world_state.coins, vec![vec2(8.0, 16.0)]);
+        let [enemy] = &world.world_state.enemies[..] else {
+            panic!("expected one enemy");
+        };
+        assert!(matches!(enemy.ty, EnemyType::Crab));
+        assert_eq!(enemy.pos, vec2(16.0, 16.0));
+        assert!(!enemy.facing_left);
+        assert_eq!(enemy.speed, 12.5);
+    }
+
+    #[test]
+    fn placed_finishes_have_no_dock() {
+        let world = level(r#"<object id="1" type="finish" x="8" y="16"/>"#).unwrap();
+        assert_eq!(world.finish_pos, None);
+        assert_eq!(world.finish_area, Rect::new(8.0, 16.0, 8.0, 8.0));
+        let dock_tiles = world
+            .layers
+            .iter()
+            .flat_map(|layer| layer.tiles.iter())
+            .filter(|(_, _, gid)| matches!(tile_id(*gid), 19 | 97))
+            .count();
+        assert_eq!(dock_tiles, 0);
+        assert_eq!(world.layers.len(), 1);
+        assert_eq!(world.bounds, Rect::new(0.0, 0.0, 32.0, 32.0));
+    }
 
-    let mut split = data.split(',');
+    #[test]
+    fn levels_without_a_finish_end_at_a_dock() {
+        let world = level("").unwrap();
+        assert_eq!(world.finish_pos, Some((3, 3)));
+        assert_eq!(world.collision().get(3, 3), 50);
+        assert_eq!(world.bounds, Rect::new(0.0, 0.0, 35.0 * 8.0, 19.0 * 8.0));
+    }
 
-    let mut chunk = vec![0; 16 * 16];
-    for item in &mut chunk {
-        let a = split.next().unwrap().trim();
-        *item = a.parse().unwrap()
+    #[test]
+    fn unknown_objects_are_errors() {
+        let err = level(r#"<object id="1" type="boss" x="8" y="8"/>"#)
+            .err()
+            .unwrap();
+        assert!(matches!(&err.kind, TmxErrorKind::UnknownObject(ty) if ty == "boss"));
+        assert_eq!(err.layer.as_deref(), Some("entities"));
+
+        let err = level(
+            r#"<object id="1" type="enemy" x="8" y="8">
+                <properties><property name="kind" value="shark"/></properties>
+            </object>"#,
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(&err.kind, TmxErrorKind::UnknownEnemy(kind) if kind == "shark"));
     }
-    Chunk { x, y, tiles: chunk }
 }
@@ -1,18 +1,13 @@
 use macroquad::prelude::*;
-use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::ToPrimitive;
 
 use crate::{
-    assets::Assets,
-    physics::{get_tile_flag, update_physicsbody},
+    assets::{Assets, TileOverrides, World},
+    levels::EnemyType,
+    physics::{Hitbox, get_tile, update_physicsbody},
     utils::GRAVITY,
 };
 
-#[derive(FromPrimitive, ToPrimitive, Clone)]
-pub enum EnemyType {
-    Snail,
-    Crab,
-}
 impl EnemyType {
     fn speed(&self) -> f32 {
         match self {
@@ -25,69 +20,64 @@ impl EnemyType {
 #[derive(Clone)]
 pub struct Enemy {
     pub pos: Vec2,
+    /// Position before the last step, which drawing interpolates from
+    pub prev_pos: Vec2,
     pub velocity: Vec2,
     pub ty: EnemyType,
+    pub speed: f32,
     pub facing_left: bool,
     pub time: f32,
     pub loaded: bool,
+    pub hitbox: Hitbox,
 }
 impl Enemy {
     pub fn new(pos: Vec2, ty: EnemyType) -> Self {
         Self {
             pos,
+            prev_pos: pos,
+            speed: ty.speed(),
             ty,
             facing_left: true,
             time: 0.0,
             velocity: Vec2::ZERO,
             loaded: false,
+            hitbox: Hitbox::TILE,
         }
     }
-    pub fn update(
-        &mut self,
-        delta_time: f32,
-        assets: &Assets,
-        broken_tiles: &[(i16, i16)],
-        current_level: usize,
-    ) {
+    pub fn update(&mut self, delta_time: f32, world: &World, tile_overrides: &TileOverrides) {
+        self.prev_pos = self.pos;
         self.time += delta_time;
-        self.velocity.y += GRAVITY * delta_time;
-        self.velocity.x = if self.facing_left { -1.0 } else { 1.0 } * self.ty.speed();
-        let old_velocity = self.velocity;
-        (self.pos, _, _, _, _) = update_physicsbody(
+        self.velocity.y += GRAVITY * world.settings.gravity_scale * delta_time;
+        self.velocity.x = if self.facing_left { -1.0 } else { 1.0 } * self.speed;
+        let result = update_physicsbody(
             self.pos,
+            self.hitbox,
             &mut self.velocity,
             delta_time,
-            &assets.levels[current_level],
-            broken_tiles,
+            world,
+            tile_overrides,
         );
-        if old_velocity.x.abs() > self.velocity.x.abs() {
+        self.pos = result.pos;
+        if result.hit_wall() {
             self.facing_left = !self.facing_left;
         }
         let tile_pos =
             (self.pos / 8.0 + vec2(if self.facing_left { -1.0 } else { 1.0 }, 1.0)).round();
-        let (tx, ty) = (tile_pos.x as i16, tile_pos.y as i16);
-        let (cx, cy) = (
-            (tx as f32 / 16.0).floor() as i16 * 16,
-            (ty as f32 / 16.0).floor() as i16 * 16,
-        );
-        if tx > 0
-            && let Some(c) = assets.levels[current_level].collision.get(&(cx, cy))
-        {
-            let tile = c
-                .tile_at((tx - cx) as usize, (ty - cy) as usize)
-                .unwrap_or(0);
-            let flags = get_tile_flag(tile);
-            if flags.is_no_collision() || flags.is_death() {
-                self.facing_left = !self.facing_left
-            }
+        // outside the level's bounds this is a wall, which the enemy turns around at instead
+        let flags = get_tile(world, tile_overrides, tile_pos.x as i16, tile_pos.y as i16)
+            .1
+            .flag;
+        if flags.is_no_collision() || flags.is_death() {
+            self.facing_left = !self.facing_left
         }
     }
-    pub fn draw(&self, assets: &Assets) {
+    pub fn draw(&self, assets: &Assets, alpha: f32) {
         let id = self.ty.to_usize().unwrap();
+        let pos = self.prev_pos.lerp(self.pos, alpha).floor();
         draw_texture_ex(
             assets.enemies.animations[id].get_at_time((self.time * 1000.0) as u32),
-            self.pos.x.floor() - 4.0,
-            self.pos.y.floor() - 8.0,
+            pos.x - 4.0,
+            pos.y - 8.0,
             WHITE,
             DrawTextureParams {
                 flip_x: self.facing_left,
@@ -0,0 +1,244 @@
+//! The game's rules, kept apart from drawing, sound and reading input, so levels can be played
+//! without a window. [`GameState::tick`] advances the game by a step and returns what happened,
+//! which the caller plays sounds and changes levels for.
+
+use macroquad::math::vec2;
+
+use crate::{
+    assets::{World, WorldState},
+    levels::LevelKind,
+    physics::Hitbox,
+    player::{Player, PlayerInput, PlayerUpdateResult},
+    utils::*,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SoundEffect {
+    Coin,
+    Stomp,
+    Jump,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GameEvent {
+    PlaySound(SoundEffect),
+    /// The player sailed off at the end of the level, so the next one should be loaded
+    LevelFinished,
+}
+
+pub struct GameState {
+    /// Index of the current level
+    pub level: usize,
+    pub world_state: WorldState,
+    pub player: Player,
+    /// Time since the level pack was started
+    pub time: f32,
+    /// Time since the current level was started or restarted
+    pub level_time: f32,
+    pub coins: u32,
+    /// Seconds the player spends dying before the level restarts
+    pub death_time: f32,
+}
+impl GameState {
+    pub fn new(levels: &[World], level: usize, death_time: f32) -> Self {
+        let (world_state, player) = start_level(&levels[level]);
+        Self {
+            level,
+            world_state,
+            player,
+            time: 0.0,
+            level_time: 0.0,
+            coins: 0,
+            death_time,
+        }
+    }
+    pub fn load_level(&mut self, levels: &[World], index: usize) {
+        self.level = index;
+        self.level_time = 0.0;
+        (self.world_state, self.player) = start_level(&levels[index]);
+    }
+    /// Advances the game by one step of `delta_time` seconds
+    pub fn tick(
+        &mut self,
+        levels: &[World],
+        input: &PlayerInput,
+        delta_time: f32,
+    ) -> Vec<GameEvent> {
+        let world = &levels[self.level];
+        let mut events = Vec::new();
+        self.time += delta_time;
+        self.level_time += delta_time;
+        self.world_state.prev_boat_offset = self.world_state.boat_offset;
+
+        match self.player.update(
+            delta_time,
+            input,
+            world,
+            &mut self.world_state,
+            self.death_time,
+        ) {
+            PlayerUpdateResult::RestartLevel => {
+                (self.world_state, self.player) = start_level(world);
+                self.level_time = 0.0;
+            }
+            PlayerUpdateResult::PlayStompSfx => {
+                events.push(GameEvent::PlaySound(SoundEffect::Stomp));
+            }
+            PlayerUpdateResult::PlayTrampolineSfx => {
+                events.push(GameEvent::PlaySound(SoundEffect::Jump));
+            }
+            PlayerUpdateResult::NextLevel => {
+                // the level is over, nothing else happens in it
+                events.push(GameEvent::LevelFinished);
+                return events;
+            }
+            PlayerUpdateResult::None => {}
+        }
+        if let Some(time_limit) = world.settings.time_limit
+            && world.info.kind != LevelKind::Menu
+            && self.player.alive()
+            && self.level_time >= time_limit
+        {
+            self.player.die();
+        }
+
+        let mut player_squashed_enemy = false;
+        self.world_state.enemies.retain_mut(|enemy| {
+            // enemies that fell out of the level are gone for good
+            if enemy.pos.y > world.bounds.bottom() {
+                return false;
+            }
+            // enemies start moving once they come into view
+            if !enemy.loaded
+                && (world.bounds.x..self.player.camera_pos.x + SCREEN_WIDTH / 2.0)
+                    .contains(&enemy.pos.x)
+            {
+                enemy.loaded = true;
+            }
+            if !enemy.loaded {
+                return true;
+            }
+            enemy.update(delta_time, world, &self.world_state.tile_overrides);
+            let player_box = self.player.hitbox.at(self.player.pos);
+            let enemy_box = enemy.hitbox.at(enemy.pos);
+            if player_squashed_enemy || !self.player.alive() || !player_box.overlaps(&enemy_box) {
+                return true;
+            }
+            player_squashed_enemy = true;
+            events.push(GameEvent::PlaySound(SoundEffect::Stomp));
+            // only landing on top of an enemy squashes it
+            if player_box.bottom() >= enemy_box.bottom() || self.player.velocity.y < 0.0 {
+                self.player.die();
+                true
+            } else {
+                self.player.velocity.y = -2.5 * 60.0;
+                false
+            }
+        });
+        self.world_state.coins.retain(|pos| {
+            if Hitbox::TILE
+                .at(*pos)
+                .overlaps(&self.player.hitbox.at(self.player.pos))
+            {
+                self.coins += 1;
+                events.push(GameEvent::PlaySound(SoundEffect::Coin));
+                false
+            } else {
+                true
+            }
+        });
+        events
+    }
+}
+
+/// A fresh copy of a level and its player
+fn start_level(world: &World) -> (WorldState, Player) {
+    let (world_state, mut player) = world.load_level();
+    if world.info.kind == LevelKind::Menu {
+        // hide the player off screen, the menu is just scenery
+        player.pos = vec2(-32.0, 0.0);
+        player.prev_pos = player.pos;
+    }
+    (world_state, player)
+}
+
+#[cfg(test)]
+mod tests {
+    use macroquad::math::Vec2;
+
+    use super::*;
+    use crate::physics::tests::world;
+
+    const DEATH_TIME: f32 = 0.5;
+
+    fn run(levels: &[World], input: PlayerInput, ticks: usize) -> (GameState, Vec<GameEvent>) {
+        let mut game = GameState::new(levels, 0, DEATH_TIME);
+        let mut events = Vec::new();
+        for _ in 0..ticks {
+            events.extend(game.tick(levels, &input, FIXED_TIMESTEP));
+        }
+        (game, events)
+    }
+
+    fn walk_right() -> PlayerInput {
+        PlayerInput {
+            axis: vec2(1.0, 0.0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn walking_right_finishes_a_flat_level() {
+        let levels = [world(&[
+            "........................................",
+            "########################################",
+        ])];
+        let (_, events) = run(&levels, walk_right(), 60 * 30);
+        assert!(events.contains(&GameEvent::LevelFinished));
+    }
+
+    #[test]
+    fn falling_in_a_gap_restarts_the_level() {
+        let mut rows = vec!["........................................"; 30];
+        rows[1] = "##########.....#########################";
+        let levels = [world(&rows)];
+        let spawn = GameState::new(&levels, 0, DEATH_TIME).player.pos;
+        let mut game = GameState::new(&levels, 0, DEATH_TIME);
+        let mut died = false;
+        for _ in 0..60 * 10 {
+            game.tick(&levels, &walk_right(), FIXED_TIMESTEP);
+            died |= !game.player.alive();
+            if died && game.player.alive() {
+                break;
+            }
+        }
+        assert!(died);
+        assert!(game.player.alive());
+        assert_eq!(game.player.pos, spawn);
+    }
+
+    #[test]
+    fn the_same_inputs_play_out_the_same() {
+        let levels = [world(&[
+            "........................................",
+            "..........#.........--..................",
+            "########################################",
+        ])];
+        let input = PlayerInput {
+            axis: vec2(1.0, 0.0),
+            jump_held: true,
+            jump_pressed: true,
+            noclip: false,
+        };
+        let positions = |ticks| -> Vec<Vec2> {
+            let mut game = GameState::new(&levels, 0, DEATH_TIME);
+            (0..ticks)
+                .map(|_| {
+                    game.tick(&levels, &input, FIXED_TIMESTEP);
+                    game.player.pos
+                })
+                .collect()
+        };
+        assert_eq!(positions(600), positions(600));
+    }
+}
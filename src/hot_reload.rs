@@ -0,0 +1,133 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use asefile::AsepriteFile;
+use macroquad::prelude::*;
+
+use crate::{assets::*, physics::TileBehaviours, tmx::parse_tileset};
+
+const ASSETS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets");
+/// How often the assets directory is checked for changes, in seconds
+const POLL_INTERVAL: f64 = 0.5;
+
+/// Watches the assets directory for changes by polling the modification times of its files.
+pub struct AssetWatcher {
+    modified: HashMap<PathBuf, SystemTime>,
+    last_poll: f64,
+}
+impl AssetWatcher {
+    pub fn new() -> Self {
+        let mut new = Self {
+            modified: HashMap::new(),
+            last_poll: get_time(),
+        };
+        new.changed_files();
+        new
+    }
+    /// Returns the files that were created or modified since the last poll
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        if get_time() - self.last_poll < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = get_time();
+        self.changed_files()
+    }
+    fn changed_files(&mut self) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        let mut dirs = vec![PathBuf::from(ASSETS_DIR)];
+        while let Some(dir) = dirs.pop() {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let Ok(modified) = entry.metadata().and_then(|m| m.modified()) else {
+                    continue;
+                };
+                if self.modified.insert(path.clone(), modified) != Some(modified) {
+                    changed.push(path);
+                }
+            }
+        }
+        changed
+    }
+}
+
+impl Assets {
+    /// Rebuilds whatever asset was loaded from a changed file.
+    /// Returns the indices of the levels that were rebuilt.
+    pub fn reload(&mut self, path: &Path) -> Result<Vec<usize>, String> {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if path.extension().is_some_and(|ext| ext == "ase") {
+            let bytes = fs::read(path).map_err(|err| err.to_string())?;
+            // the editor might not be done writing the file yet
+            AsepriteFile::read(&bytes[..]).map_err(|err| err.to_string())?;
+            match name.as_str() {
+                "player.ase" => self.player = AnimationsGroup::from_file(&bytes),
+                "enemies.ase" => self.enemies = AnimationsGroup::from_file(&bytes),
+                "coin.ase" => self.coin = Animation::from_file(&bytes),
+                "start_btn.ase" => self.start_btn = Animation::from_file(&bytes),
+                "plus_btn.ase" => self.plus_btn = Animation::from_file(&bytes),
+                "minus_btn.ase" => self.minus_btn = Animation::from_file(&bytes),
+                "tileset.ase" => self.tileset.texture = load_ase_texture(&bytes, None),
+                "numbers.ase" => self.numbers.texture = load_ase_texture(&bytes, None),
+                "boat.ase" => self.boat = load_ase_texture(&bytes, None),
+                "menu_body.ase" => self.menu_body = load_ase_texture(&bytes, None),
+                "bar_ui.ase" => self.bar_ui = load_ase_texture(&bytes, None),
+                "win_screen.ase" => self.win_screen = load_ase_texture(&bytes, None),
+                _ => return Ok(Vec::new()),
+            }
+            info!("reloaded {name}");
+            return Ok(Vec::new());
+        }
+
+        let levels: Vec<usize> = if name == "tileset.tsx" {
+            let xml = fs::read_to_string(path).map_err(|err| err.to_string())?;
+            let tileset = parse_tileset(&name, &xml).map_err(|err| err.to_string())?;
+            let tiles = TileBehaviours::from_tileset(&tileset).map_err(|err| err.to_string())?;
+            self.tile_animations =
+                TileAnimations::from_tileset(&tileset).map_err(|err| err.to_string())?;
+            self.tiles = tiles;
+            for level in self.levels.iter_mut() {
+                level.tiles = self.tiles.clone();
+            }
+            (0..self.levels.len())
+                .filter(|index| self.levels[*index].info.pack == 0)
+                .collect()
+        } else if path.extension().is_some_and(|ext| ext == "tmx") {
+            (0..self.levels.len())
+                .filter(|index| {
+                    let info = &self.levels[*index].info;
+                    info.pack == 0 && info.file == name
+                })
+                .collect()
+        } else {
+            return Ok(Vec::new());
+        };
+        for index in levels.iter() {
+            let info = self.levels[*index].info.clone();
+            let path = Path::new(ASSETS_DIR).join("levels").join(&info.file);
+            let xml = fs::read_to_string(&path).map_err(|err| err.to_string())?;
+            let mut level =
+                World::from_data(&info.file, &xml, &self.tiles).map_err(|err| err.to_string())?;
+            level.set_info(info);
+            if !self.music.contains_key(&level.settings.music) {
+                return Err(format!("music \"{}\" doesn't exist", level.settings.music));
+            }
+            self.levels[*index] = level;
+        }
+        info!("reloaded {name}");
+        Ok(levels)
+    }
+}
@@ -0,0 +1,340 @@
+//! The game specific parts of level files: manifests, map settings and the objects levels
+//! place. Nothing here needs macroquad, so build.rs checks the built in levels with the same
+//! code the game loads them with.
+
+use std::{error::Error, fs, path::Path};
+
+use num_derive::{FromPrimitive, ToPrimitive};
+use roxmltree::Document;
+
+use crate::tmx::{Map, Properties, TmxError, TmxErrorKind};
+
+/// Names of the music tracks built into the game, which levels pick from with `music`
+pub const MUSIC_TRACKS: &[&str] = &["song"];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LevelKind {
+    Menu,
+    Playable,
+    Ending,
+}
+
+/// A level's entry in the manifest.
+#[derive(Clone, Debug)]
+pub struct LevelInfo {
+    pub file: String,
+    pub name: String,
+    pub kind: LevelKind,
+    /// Index of the level pack the level belongs to, where 0 is the built in campaign
+    pub pack: usize,
+    /// Overrides the music set in the level itself
+    pub music: Option<String>,
+    /// Time in seconds a good run of the level should take
+    pub par_time: Option<f32>,
+}
+impl LevelInfo {
+    /// Info for a level that isn't listed in a manifest
+    pub fn from_file(file: &str) -> Self {
+        let name = file.rsplit('/').next().unwrap_or(file);
+        Self {
+            file: file.to_string(),
+            name: name.strip_suffix(".tmx").unwrap_or(name).to_string(),
+            kind: LevelKind::Playable,
+            pack: 0,
+            music: None,
+            par_time: None,
+        }
+    }
+}
+
+/// Settings a level sets with custom properties on the map.
+#[derive(Clone, Debug)]
+pub struct LevelSettings {
+    /// As rgba
+    pub background_color: [u8; 4],
+    pub music: String,
+    /// Seconds the player has to finish the level before dying
+    pub time_limit: Option<f32>,
+    /// Multiplies the gravity of the player and enemies
+    pub gravity_scale: f32,
+    /// Area the player and camera are kept in, as x, y, width and height in pixels. Defaults
+    /// to the area the level's tiles are in.
+    pub bounds: Option<[f32; 4]>,
+}
+impl Default for LevelSettings {
+    fn default() -> Self {
+        Self {
+            background_color: [0x00, 0xaa, 0xff, 0xff],
+            music: "song".to_string(),
+            time_limit: None,
+            gravity_scale: 1.0,
+            bounds: None,
+        }
+    }
+}
+impl LevelSettings {
+    pub fn from_properties(properties: &Properties) -> Result<Self, TmxErrorKind> {
+        let mut settings = Self::default();
+        if let Some(color) = properties.get::<String>("background_color")? {
+            settings.background_color = parse_color(&color).ok_or_else(|| {
+                TmxErrorKind::InvalidProperty("background_color".to_string(), color)
+            })?;
+        }
+        if let Some(music) = properties.get("music")? {
+            settings.music = music;
+        }
+        settings.time_limit = properties.get("time_limit")?;
+        if let Some(gravity_scale) = properties.get("gravity_scale")? {
+            settings.gravity_scale = gravity_scale;
+        }
+        if let Some(bounds) = properties.get::<String>("bounds")? {
+            settings.bounds = Some(
+                parse_bounds(&bounds)
+                    .ok_or_else(|| TmxErrorKind::InvalidProperty("bounds".to_string(), bounds))?,
+            );
+        }
+        Ok(settings)
+    }
+}
+
+#[derive(FromPrimitive, ToPrimitive, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EnemyType {
+    Snail,
+    Crab,
+}
+impl EnemyType {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "snail" => Some(EnemyType::Snail),
+            "crab" => Some(EnemyType::Crab),
+            _ => None,
+        }
+    }
+}
+
+/// Tiles placed in the special layer to mark where a level starts and ends.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TileMarker {
+    Spawn,
+    Finish,
+}
+impl TileMarker {
+    /// Reads the `marker` property of a tileset's tile.
+    pub fn from_properties(properties: &Properties) -> Result<Option<Self>, TmxErrorKind> {
+        let Some(marker) = properties.get::<String>("marker")? else {
+            return Ok(None);
+        };
+        match marker.as_str() {
+            "spawn" => Ok(Some(TileMarker::Spawn)),
+            "finish" => Ok(Some(TileMarker::Finish)),
+            _ => Err(TmxErrorKind::InvalidProperty("marker".to_string(), marker)),
+        }
+    }
+}
+
+/// Something a level places with an object.
+#[derive(Clone, Debug)]
+pub enum LevelObject {
+    Coin,
+    Enemy {
+        ty: EnemyType,
+        facing_left: Option<bool>,
+        speed: Option<f32>,
+    },
+    Spawn,
+    /// The area that finishes the level
+    Finish {
+        width: f32,
+        height: f32,
+    },
+}
+
+/// What the game reads from a map besides its tiles.
+pub struct LevelData {
+    pub settings: LevelSettings,
+    /// Objects with the position of their top left corner, in pixels
+    pub objects: Vec<(f32, f32, LevelObject)>,
+}
+impl LevelData {
+    /// Reads a map's settings and objects, and checks it has the layers every level needs and
+    /// that its tiles are close enough to the origin for the game to store.
+    pub fn from_map(map: &Map) -> Result<Self, TmxError> {
+        let settings = LevelSettings::from_properties(&map.properties)
+            .map_err(|kind| TmxError::new(&map.file, None, map.line, kind))?;
+        map.required_layer("collision")?;
+        for layer in map.layers.iter() {
+            for chunk in layer.chunks.iter() {
+                let out_of_range = chunk.tiles.iter().enumerate().find_map(|(index, tile)| {
+                    let x = chunk.x + (index as u32 % chunk.width) as i32;
+                    let y = chunk.y + (index as u32 / chunk.width) as i32;
+                    let fits = i16::try_from(x).is_ok() && i16::try_from(y).is_ok();
+                    (*tile != 0 && !fits).then_some((x, y))
+                });
+                if let Some((x, y)) = out_of_range {
+                    return Err(TmxError::new(
+                        &map.file,
+                        Some(&layer.name),
+                        chunk.line,
+                        TmxErrorKind::TileOutOfRange(x, y),
+                    ));
+                }
+            }
+        }
+        let mut objects = Vec::new();
+        for layer in map.object_layers.iter() {
+            for object in layer.objects.iter() {
+                let error = |kind| TmxError::new(&map.file, Some(&layer.name), object.line, kind);
+                let (x, mut y) = (object.x, object.y);
+                // tile objects are positioned by their bottom left corner
+                if object.gid.is_some() {
+                    y -= object.height;
+                }
+                let level_object = match object.ty.as_str() {
+                    "coin" => LevelObject::Coin,
+                    "enemy" => {
+                        let kind: String = object
+                            .properties
+                            .get("kind")
+                            .map_err(error)?
+                            .unwrap_or_default();
+                        let ty = EnemyType::from_name(&kind)
+                            .ok_or_else(|| error(TmxErrorKind::UnknownEnemy(kind)))?;
+                        let facing_left = match object
+                            .properties
+                            .get::<String>("facing")
+                            .map_err(error)?
+                            .as_deref()
+                        {
+                            None => None,
+                            Some("left") => Some(true),
+                            Some("right") => Some(false),
+                            Some(facing) => {
+                                return Err(error(TmxErrorKind::InvalidProperty(
+                                    "facing".to_string(),
+                                    facing.to_string(),
+                                )));
+                            }
+                        };
+                        LevelObject::Enemy {
+                            ty,
+                            facing_left,
+                            speed: object.properties.get("speed").map_err(error)?,
+                        }
+                    }
+                    "spawn" => LevelObject::Spawn,
+                    // point objects have no size, so they finish the level like a tile
+                    "finish" if object.width == 0.0 && object.height == 0.0 => {
+                        LevelObject::Finish {
+                            width: 8.0,
+                            height: 8.0,
+                        }
+                    }
+                    "finish" => LevelObject::Finish {
+                        width: object.width,
+                        height: object.height,
+                    },
+                    // objects without a type can be used for notes in the editor
+                    "" => continue,
+                    ty => return Err(error(TmxErrorKind::UnknownObject(ty.to_string()))),
+                };
+                objects.push((x, y, level_object));
+            }
+        }
+        Ok(Self { settings, objects })
+    }
+}
+
+/// Parses a colour in the `#rrggbb` or `#aarrggbb` format Tiled uses.
+fn parse_color(text: &str) -> Option<[u8; 4]> {
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    let [a, r, g, b] = match hex.len() {
+        6 => (value | 0xff000000).to_be_bytes(),
+        8 => value.to_be_bytes(),
+        _ => return None,
+    };
+    Some([r, g, b, a])
+}
+
+/// Parses bounds written as `x, y, width, height` in tiles.
+fn parse_bounds(text: &str) -> Option<[f32; 4]> {
+    let values = text
+        .split(',')
+        .map(|value| value.trim().parse::<f32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let [x, y, width, height] = values[..] else {
+        return None;
+    };
+    (width > 0.0 && height > 0.0).then_some([x * 8.0, y * 8.0, width * 8.0, height * 8.0])
+}
+
+/// Parses a level manifest, which lists the levels of the campaign in the order they're played.
+pub fn parse_manifest(file: &str, xml: &str) -> Result<Vec<LevelInfo>, TmxError> {
+    let doc = Document::parse(xml)
+        .map_err(|err| TmxError::new(file, None, err.pos().row, TmxErrorKind::Xml(err)))?;
+    let mut levels = Vec::new();
+    for node in doc.root_element().children().filter(|n| n.is_element()) {
+        let line = doc.text_pos_at(node.range().start).row;
+        let error = |kind| TmxError::new(file, None, line, kind);
+        if !node.has_tag_name("level") {
+            return Err(error(TmxErrorKind::UnexpectedElement(
+                node.tag_name().name().to_string(),
+            )));
+        }
+        let attribute = |name| {
+            node.attribute(name)
+                .ok_or_else(|| error(TmxErrorKind::MissingAttribute(name)))
+        };
+        let level_file = attribute("file")?;
+        let mut info = LevelInfo::from_file(level_file);
+        if let Some(name) = node.attribute("name") {
+            info.name = name.to_string();
+        }
+        if let Some(music) = node.attribute("music") {
+            info.music = Some(music.to_string());
+        }
+        info.kind = match attribute("kind")? {
+            "menu" => LevelKind::Menu,
+            "playable" => LevelKind::Playable,
+            "ending" => LevelKind::Ending,
+            kind => {
+                return Err(error(TmxErrorKind::InvalidAttribute(
+                    "kind",
+                    kind.to_string(),
+                )));
+            }
+        };
+        if let Some(par_time) = node.attribute("par_time") {
+            info.par_time = Some(par_time.parse().map_err(|_| {
+                error(TmxErrorKind::InvalidAttribute(
+                    "par_time",
+                    par_time.to_string(),
+                ))
+            })?);
+        }
+        levels.push(info);
+    }
+    Ok(levels)
+}
+
+/// Lists the levels of a level pack directory, with their paths relative to it.
+/// Uses the directory's `levels.xml` if it has one, otherwise every `.tmx` file in name order.
+pub fn read_pack_dir(dir: &Path) -> Result<Vec<LevelInfo>, Box<dyn Error>> {
+    let manifest = dir.join("levels.xml");
+    if manifest.is_file() {
+        let xml = fs::read_to_string(&manifest)?;
+        return Ok(parse_manifest(&manifest.to_string_lossy(), &xml)?);
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "tmx") {
+            files.push(path.file_name().unwrap().to_string_lossy().to_string());
+        }
+    }
+    files.sort();
+    Ok(files
+        .iter()
+        .map(|file| LevelInfo::from_file(file))
+        .collect())
+}
@@ -0,0 +1,262 @@
+//! Checks levels for mistakes that don't stop them from loading, for `--check-levels`.
+//! Runs without a window, so CI can run it too.
+
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use macroquad::prelude::*;
+use num_traits::FromPrimitive;
+
+use crate::{
+    assets::*,
+    levels::{EnemyType, parse_manifest, read_pack_dir},
+    physics::{Hitbox, TileBehaviours, TileFlag, get_tile, tile_span},
+    tmx::{Map, decode_maps, parse_map, parse_tileset, tile_id},
+    utils::*,
+};
+
+/// Checks the built in levels and the level packs in `dirs`, printing every problem found.
+/// Returns whether all levels passed.
+pub fn check_levels(dirs: &[PathBuf]) -> bool {
+    let tiles = parse_tileset("tileset.tsx", BUILTIN_TILESET)
+        .and_then(|tileset| TileBehaviours::from_tileset(&tileset))
+        .unwrap_or_else(|err| panic!("{err}"));
+    let mut problems = 0;
+    let mut report = |problem: String| {
+        println!("{problem}");
+        problems += 1;
+    };
+    let mut count = 0;
+
+    let maps = decode_maps("levels.bin", BUILTIN_LEVELS).unwrap_or_else(|err| panic!("{err}"));
+    match parse_manifest("levels.xml", BUILTIN_MANIFEST) {
+        Ok(manifest) => {
+            for info in manifest {
+                match maps.iter().find(|map| map.file == info.file) {
+                    Some(map) => check_map(map, &tiles, &mut report),
+                    None => report(format!("levels.xml: {} is missing", info.file)),
+                }
+                count += 1;
+            }
+        }
+        Err(err) => report(err.to_string()),
+    }
+
+    for dir in dirs {
+        let infos = match read_pack_dir(dir) {
+            Ok(infos) => infos,
+            Err(err) => {
+                report(format!("{}: {err}", dir.display()));
+                continue;
+            }
+        };
+        for info in infos {
+            let path = dir.join(&info.file);
+            let name = path.to_string_lossy();
+            let map = fs::read_to_string(&path)
+                .map_err(|err| format!("{name}: {err}"))
+                .and_then(|xml| parse_map(&name, &xml).map_err(|err| err.to_string()));
+            match map {
+                Ok(map) => check_map(&map, &tiles, &mut report),
+                Err(err) => report(err),
+            }
+            count += 1;
+        }
+    }
+
+    println!("checked {count} levels, found {problems} problems");
+    problems == 0
+}
+
+fn check_map(map: &Map, tiles: &TileBehaviours, report: &mut impl FnMut(String)) {
+    let world = match World::from_map(map, tiles) {
+        Ok(world) => world,
+        Err(err) => {
+            report(err.to_string());
+            return;
+        }
+    };
+    let mut report = |problem: String| report(format!("{}: {problem}", map.file));
+
+    if map.infinite {
+        for layer in map.layers.iter() {
+            for chunk in layer.chunks.iter() {
+                if chunk.width != 16 || chunk.tiles.len() != 16 * 16 {
+                    report(format!(
+                        "chunk at {}, {} in layer \"{}\" (line {}) is {}x{}, not 16x16",
+                        chunk.x,
+                        chunk.y,
+                        layer.name,
+                        chunk.line,
+                        chunk.width,
+                        chunk.tiles.len() / chunk.width.max(1) as usize,
+                    ));
+                }
+            }
+        }
+    }
+
+    for (x, y, gid) in world.special.iter() {
+        let id = tile_id(gid);
+        if world.tiles.get(id).marker.is_none()
+            && (2..18).contains(&id)
+            && EnemyType::from_u32(id - 2).is_none()
+        {
+            report(format!("unknown enemy tile {id} at {x}, {y}"));
+        }
+    }
+
+    let (world_state, player) = world.load_level();
+    let outside = |hitbox: Rect| !world.bounds.overlaps(&hitbox);
+    let player_box = player.hitbox.at(player.pos);
+    if outside(player_box) {
+        report(format!(
+            "spawn at {}, {} is outside the level bounds",
+            player.pos.x, player.pos.y
+        ));
+    } else if let Some(tile) = solid_tile_under(&world, player_box) {
+        report(format!(
+            "spawn at {}, {} is stuck inside the solid tile at {}, {}",
+            player.pos.x, player.pos.y, tile.0, tile.1
+        ));
+    }
+    for coin in world_state.coins.iter() {
+        let coin_box = Hitbox::TILE.at(*coin);
+        if outside(coin_box) {
+            report(format!(
+                "coin at {}, {} is outside the level bounds",
+                coin.x, coin.y
+            ));
+        } else if let Some(tile) = solid_tile_under(&world, coin_box) {
+            report(format!(
+                "coin at {}, {} is inside the solid tile at {}, {}",
+                coin.x, coin.y, tile.0, tile.1
+            ));
+        }
+    }
+    if let Err(problem) = check_finish_reachable(&world, player.pos) {
+        report(problem);
+    }
+}
+
+/// Finds a solid tile overlapping a hitbox
+fn solid_tile_under(world: &World, hitbox: Rect) -> Option<(i16, i16)> {
+    for y in tile_span(hitbox.y, hitbox.h) {
+        for x in tile_span(hitbox.x, hitbox.w) {
+            if get_tile(world, &TileOverrides::default(), x, y)
+                .1
+                .flag
+                .is_collision()
+            {
+                return Some((x, y));
+            }
+        }
+    }
+    None
+}
+
+/// Checks the finish can be reached from the spawn by walking and jumping.
+/// Walls, ceilings and enemies are ignored, so this only catches levels that are impossible
+/// for sure, like ones with gaps too wide or ledges too high to jump.
+fn check_finish_reachable(world: &World, spawn: Vec2) -> Result<(), String> {
+    let collision = world.collision();
+    let overrides = TileOverrides::default();
+    let flag = |x, y| get_tile(world, &overrides, x, y).1.flag;
+
+    // every empty tile the player can stand in, and every trampoline, with how high they bounce
+    let mut standing = HashMap::new();
+    for (x, y, gid) in collision.iter() {
+        let tile = world.tiles.get(gid);
+        if tile.bounce.is_some() {
+            standing.insert((x, y), tile.bounce);
+        }
+        if matches!(tile.flag, TileFlag::Collision | TileFlag::OneWayCollision)
+            && matches!(
+                flag(x, y - 1),
+                TileFlag::NoCollision | TileFlag::OneWayCollision
+            )
+        {
+            standing.entry((x, y - 1)).or_insert(None);
+        }
+    }
+    let standing: Vec<_> = standing.into_iter().collect();
+
+    let (spawn_x, spawn_y) = (
+        (spawn.x / 8.0).round() as i16,
+        (spawn.y / 8.0).floor() as i16,
+    );
+    let Some(start) = standing
+        .iter()
+        .filter(|((x, y), _)| *x == spawn_x && *y >= spawn_y)
+        .min_by_key(|((_, y), _)| *y)
+        .map(|(pos, _)| *pos)
+    else {
+        return Err(format!(
+            "spawn at {}, {} has no ground below it",
+            spawn.x, spawn.y
+        ));
+    };
+
+    let gravity = GRAVITY * world.settings.gravity_scale;
+    let jump = jump_arc(2.3 * 60.0, gravity, true);
+    let mut reached = vec![false; standing.len()];
+    let mut queue = Vec::new();
+    if let Some(index) = standing.iter().position(|(pos, _)| *pos == start) {
+        reached[index] = true;
+        queue.push(index);
+    }
+    let finish = world.finish_area;
+    let finish_tile = (
+        (finish.x / 8.0).floor() as i16,
+        ((finish.bottom().min(1.0e6) - 1.0) / 8.0).floor() as i16,
+    );
+    while let Some(index) = queue.pop() {
+        let ((x, y), bounce) = standing[index];
+        let arc = match bounce {
+            Some(bounce) => &jump_arc(bounce * 60.0, gravity, false),
+            None => &jump,
+        };
+        if Rect::new(x as f32 * 8.0, y as f32 * 8.0, 8.0, 8.0).overlaps(&finish)
+            || can_jump(arc, (x, y), finish_tile)
+        {
+            return Ok(());
+        }
+        for (other, (pos, _)) in standing.iter().enumerate() {
+            if !reached[other] && can_jump(arc, (x, y), *pos) {
+                reached[other] = true;
+                queue.push(other);
+            }
+        }
+    }
+    Err("the finish can't be reached from the spawn".to_string())
+}
+
+/// Positions relative to the start of the highest, longest jump, one per frame.
+/// `hold` is whether jump can be held to jump higher, which isn't the case for trampolines.
+fn jump_arc(velocity: f32, gravity: f32, hold: bool) -> Vec<Vec2> {
+    // the fastest the player can move through the air
+    let speed = ACCELERATION / AIR_DRAG;
+    let delta_time = 1.0 / 60.0;
+    let mut pos = Vec2::ZERO;
+    let mut velocity = vec2(speed, -velocity);
+    let mut time = 0.0;
+    let mut arc = Vec::new();
+    // long enough to fall off the bottom of any level
+    while pos.y < 512.0 * 8.0 && arc.len() < 60 * 60 {
+        time += delta_time;
+        // holding jump slows down gravity for a while
+        if hold && time < 0.5 {
+            velocity.y -= 30.0 * 10.0 * delta_time;
+        }
+        velocity.y += gravity * delta_time;
+        pos += velocity * delta_time;
+        arc.push(pos);
+    }
+    arc
+}
+
+fn can_jump(arc: &[Vec2], from: (i16, i16), to: (i16, i16)) -> bool {
+    // landing with any part of the player on the tile is enough
+    let distance = (((to.0 as f32 - from.0 as f32).abs() - 1.0) * 8.0 + 1.0).max(0.0);
+    let drop = (to.1 as f32 - from.1 as f32) * 8.0;
+    arc.iter().any(|pos| pos.x >= distance && pos.y <= drop)
+}
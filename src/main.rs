@@ -1,229 +1,272 @@
-use std::f32::consts::E;
+use std::{
+    env::args,
+    f32::consts::E,
+    path::{Path, PathBuf},
+};
 
 use macroquad::{
-    audio::{PlaySoundParams, play_sound, set_sound_volume},
+    audio::{PlaySoundParams, play_sound, set_sound_volume, stop_sound},
     miniquad::window::screen_size,
     prelude::*,
 };
 
 use crate::{
     assets::*,
-    player::{Player, PlayerUpdateResult},
+    game::{GameEvent, GameState, SoundEffect},
+    levels::LevelKind,
+    player::{PlayerInput, death_time},
+    tile_cache::TileCache,
     utils::*,
 };
 
 mod assets;
 mod enemy;
+mod game;
+#[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+mod hot_reload;
+mod levels;
+mod lint;
 mod physics;
 mod player;
+mod render;
+mod tile_cache;
+mod tmx;
 mod utils;
 
-struct Gnobbler<'a> {
+struct Gnobbler {
     in_main_menu: bool,
-    assets: &'a Assets,
-    player: Player,
+    assets: Assets,
+    game: GameState,
     camera: Camera2D,
-    world_state: WorldState,
-    time: f32,
-    current_level: usize,
+    tile_cache: TileCache,
+    input: PlayerInput,
+    /// Time that hasn't been stepped through yet
+    accumulator: f32,
+    selected_pack: usize,
+    music: Option<String>,
     volume: f32,
     actual_volume: f32,
-    coins: u32,
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    watcher: hot_reload::AssetWatcher,
 }
-impl<'a> Gnobbler<'a> {
-    fn new(assets: &'a Assets, default_volume: f32) -> Self {
-        let (world_state, mut player) = assets.levels[0].load_level();
-        player.pos = vec2(-32.0, 0.0);
-        let mut camera = create_camera(SCREEN_WIDTH, SCREEN_HEIGHT);
-        camera.target = vec2(SCREEN_WIDTH / 2.0, SCREEN_HEIGHT / 2.0);
+impl Gnobbler {
+    fn new(assets: Assets, default_volume: f32) -> Self {
+        let game = GameState::new(&assets.levels, 0, death_time(&assets));
+        let camera = create_camera(SCREEN_WIDTH, SCREEN_HEIGHT);
 
         let mut new = Self {
-            coins: 0,
             in_main_menu: true,
-            player,
-            world_state,
+            game,
+            tile_cache: TileCache::new(),
+            input: PlayerInput::default(),
+            accumulator: 0.0,
             camera,
             assets,
-            time: 0.0,
-            current_level: 0,
+            selected_pack: 0,
+            music: None,
             volume: 0.0,
             actual_volume: 0.0,
+            #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+            watcher: hot_reload::AssetWatcher::new(),
         };
         new.set_volume(default_volume);
+        new.load_level(0);
         new
     }
     fn set_volume(&mut self, new: f32) {
         let actual = new.powf(E);
-        set_sound_volume(&self.assets.song, actual);
+        if let Some(music) = &self.music {
+            set_sound_volume(&self.assets.music[music], actual);
+        }
         self.actual_volume = actual;
         self.volume = new;
     }
-    fn draw_world(&self) {
-        for layer in [
-            &self.assets.levels[self.current_level].background,
-            &self.assets.levels[self.current_level].collision,
-            &self.assets.levels[self.current_level].details,
-        ] {
-            for ((cx, cy), chunk) in layer.iter() {
-                for (index, tile) in chunk.tiles.iter().enumerate() {
-                    if *tile == 0 {
-                        continue;
-                    }
-                    let tile = *tile - 1;
-                    let x = index % 16;
-                    let y = index / 16;
-                    if tile == 48
-                        && self
-                            .world_state
-                            .broken_tiles
-                            .contains(&(*cx + x as i16, *cy + y as i16))
-                    {
-                        continue;
+    fn play_music(&mut self, name: &str) {
+        if self.music.as_deref() == Some(name) {
+            return;
+        }
+        if let Some(old) = self.music.take() {
+            stop_sound(&self.assets.music[&old]);
+        }
+        play_sound(
+            &self.assets.music[name],
+            PlaySoundParams {
+                looped: true,
+                volume: self.actual_volume,
+            },
+        );
+        self.music = Some(name.to_string());
+    }
+    fn load_level(&mut self, index: usize) {
+        self.game.load_level(&self.assets.levels, index);
+        let level = &self.assets.levels[index];
+        self.in_main_menu = level.info.kind == LevelKind::Menu;
+        if self.in_main_menu {
+            self.camera.target = vec2(SCREEN_WIDTH / 2.0, SCREEN_HEIGHT / 2.0);
+        }
+        let music = level.settings.music.clone();
+        self.play_music(&music);
+    }
+    /// Index of the level that follows the current one. Endings, and the end of a level pack,
+    /// lead back to the first menu. Menus lead to the selected level pack.
+    fn next_level(&self) -> usize {
+        let levels = &self.assets.levels;
+        let current = &levels[self.game.level].info;
+        if current.kind == LevelKind::Menu
+            && let Some(first) = levels.iter().position(|level| {
+                level.info.pack == self.selected_pack && level.info.kind != LevelKind::Menu
+            })
+        {
+            return first;
+        }
+        let next = self.game.level + 1;
+        if current.kind == LevelKind::Ending
+            || next >= levels.len()
+            || levels[next].info.pack != current.pack
+        {
+            levels
+                .iter()
+                .position(|level| level.info.kind == LevelKind::Menu)
+                .unwrap_or(0)
+        } else {
+            next
+        }
+    }
+    /// Rebuilds assets whose files changed on disk, restarting the current level in place
+    /// if it was one of them.
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    fn hot_reload(&mut self) {
+        for path in self.watcher.poll() {
+            match self.assets.reload(&path) {
+                Ok(levels) => {
+                    // the level or the tileset its chunks were rendered with changed
+                    self.tile_cache.clear();
+                    self.game.death_time = death_time(&self.assets);
+                    if levels.contains(&self.game.level) {
+                        // keep the camera where it is, so the level doesn't jump around
+                        let camera = (
+                            self.game.player.camera_pos,
+                            self.game.player.prev_camera_pos,
+                        );
+                        self.game.load_level(&self.assets.levels, self.game.level);
+                        (
+                            self.game.player.camera_pos,
+                            self.game.player.prev_camera_pos,
+                        ) = camera;
                     }
-                    self.assets.tileset.draw_tile(
-                        *cx as f32 * 8.0 + (x * 8) as f32,
-                        *cy as f32 * 8.0 + (y * 8) as f32,
-                        (tile % 16) as f32,
-                        (tile / 16) as f32,
-                        None,
-                    );
                 }
+                Err(err) => warn!("couldn't reload {}: {err}", path.display()),
             }
         }
     }
     fn update(&mut self) {
-        // cap delta time to a minimum of 60 fps.
-        let delta_time = get_frame_time().min(1.0 / 60.0);
-        self.time += delta_time;
-        let (actual_screen_width, actual_screen_height) = screen_size();
-        let scale_factor =
-            (actual_screen_width / SCREEN_WIDTH).min(actual_screen_height / SCREEN_HEIGHT);
+        #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+        self.hot_reload();
 
-        let result = self.player.update(
-            delta_time,
-            self.assets,
-            &mut self.world_state,
-            self.current_level,
-        );
-
-        match result {
-            PlayerUpdateResult::RestartLevel => {
-                (self.world_state, self.player) =
-                    self.assets.levels[self.current_level].load_level();
-            }
-            PlayerUpdateResult::PlayStompSfx => {
-                play_sound(
-                    &self.assets.stomp_sfx,
-                    PlaySoundParams {
-                        looped: false,
-                        volume: self.actual_volume,
-                    },
-                );
-            }
-            PlayerUpdateResult::PlayTrampolineSfx => {
-                play_sound(
-                    &self.assets.jump_sfx,
-                    PlaySoundParams {
-                        looped: false,
-                        volume: self.actual_volume,
-                    },
-                );
-            }
-            PlayerUpdateResult::NextLevel => {
-                self.current_level += 1;
-                (self.world_state, self.player) =
-                    self.assets.levels[self.current_level].load_level();
+        // the game runs in fixed steps however fast frames are drawn, so it plays the same
+        // at any frame rate. long frames are capped, so a stall doesn't take ages to catch up on
+        self.input.poll();
+        self.accumulator += get_frame_time().min(MAX_FRAME_TIME);
+        while self.accumulator >= FIXED_TIMESTEP {
+            self.accumulator -= FIXED_TIMESTEP;
+            self.tick(FIXED_TIMESTEP);
+            self.input.jump_pressed = false;
+        }
+        self.draw(self.accumulator / FIXED_TIMESTEP);
+    }
+    /// Advances the game by one step, then plays its sounds and loads the next level if it
+    /// was finished
+    fn tick(&mut self, delta_time: f32) {
+        for event in self.game.tick(&self.assets.levels, &self.input, delta_time) {
+            match event {
+                GameEvent::PlaySound(sound) => {
+                    let sound = match sound {
+                        SoundEffect::Coin => &self.assets.coin_sfx,
+                        SoundEffect::Stomp => &self.assets.stomp_sfx,
+                        SoundEffect::Jump => &self.assets.jump_sfx,
+                    };
+                    play_sound(
+                        sound,
+                        PlaySoundParams {
+                            looped: false,
+                            volume: self.actual_volume,
+                        },
+                    );
+                }
+                GameEvent::LevelFinished => {
+                    let info = &self.assets.levels[self.game.level].info;
+                    if let Some(par_time) = info.par_time {
+                        info!(
+                            "finished {} in {:.1}s, par is {par_time:.1}s",
+                            info.name, self.game.level_time
+                        );
+                    }
+                    self.load_level(self.next_level());
+                }
             }
-            PlayerUpdateResult::None => {}
         }
-
+    }
+    /// Draws the game and the menu. Things that move are drawn `alpha` of the way from where
+    /// they were before the last step to where they are now, so motion stays smooth when
+    /// frames don't line up with steps.
+    fn draw(&mut self, alpha: f32) {
+        let (actual_screen_width, actual_screen_height) = screen_size();
+        let scale_factor =
+            (actual_screen_width / SCREEN_WIDTH).min(actual_screen_height / SCREEN_HEIGHT);
         if !self.in_main_menu {
-            self.camera.target = self.player.camera_pos.floor();
+            self.camera.target = self
+                .game
+                .player
+                .prev_camera_pos
+                .lerp(self.game.player.camera_pos, alpha)
+                .floor();
         }
         set_camera(&self.camera);
-        clear_background(Color::from_hex(0x00aaff));
-        self.draw_world();
-        let pos = self.assets.levels[self.current_level].finish_pos;
-        let mut pos = vec2((pos.0 * 8) as f32 + 8.0, (pos.1 * 8) as f32 - 32.0);
-        if self.world_state.boat_offset > BOAT_WAIT_TIME {
-            pos.x += (self.world_state.boat_offset - BOAT_WAIT_TIME) * BOAT_MOVE_SPEED;
-        }
-        draw_texture(&self.assets.boat, pos.x, pos.y, WHITE);
-        let mut player_squashed_enemy = false;
-        self.world_state.enemies.retain_mut(|enemy| {
-            if !enemy.loaded
-                && (0.0..self.player.camera_pos.x + SCREEN_WIDTH / 2.0).contains(&enemy.pos.x)
-            {
-                enemy.loaded = true;
-            }
-            if enemy.loaded {
-                enemy.update(
-                    delta_time,
-                    self.assets,
-                    &self.world_state.broken_tiles,
-                    self.current_level,
-                );
-                enemy.draw(self.assets);
-                if !player_squashed_enemy
-                    && self.player.alive()
-                    && self.player.pos.distance_squared(enemy.pos) < 64.0
-                {
-                    player_squashed_enemy = true;
-                    if self.player.pos.y >= enemy.pos.y || self.player.velocity.y < 0.0 {
-                        play_sound(
-                            &self.assets.stomp_sfx,
-                            PlaySoundParams {
-                                looped: false,
-                                volume: self.actual_volume,
-                            },
-                        );
-                        self.player.die();
-                        true
-                    } else {
-                        play_sound(
-                            &self.assets.stomp_sfx,
-                            PlaySoundParams {
-                                looped: false,
-                                volume: self.actual_volume,
-                            },
-                        );
-                        self.player.velocity.y = -2.5 * 60.0;
-                        false
-                    }
-                } else {
-                    true
-                }
-            } else {
-                true
+        let [r, g, b, a] = self.assets.levels[self.game.level]
+            .settings
+            .background_color;
+        clear_background(Color::from_rgba(r, g, b, a));
+        self.tile_cache.draw(
+            &self.assets,
+            self.game.level,
+            &self.game.world_state,
+            &self.camera,
+            self.game.time,
+        );
+        if let Some(pos) = self.assets.levels[self.game.level].finish_pos {
+            let mut pos = vec2((pos.0 * 8) as f32 + 8.0, (pos.1 * 8) as f32 - 32.0);
+            let boat_offset = self.game.world_state.prev_boat_offset
+                + (self.game.world_state.boat_offset - self.game.world_state.prev_boat_offset)
+                    * alpha;
+            if boat_offset > BOAT_WAIT_TIME {
+                pos.x += (boat_offset - BOAT_WAIT_TIME) * BOAT_MOVE_SPEED;
             }
-        });
-        self.world_state.coins.retain(|(x, y)| {
-            let pos = vec2(*x as f32 * 8.0, *y as f32 * 8.0);
+            draw_texture(&self.assets.boat, pos.x, pos.y, WHITE);
+        }
+        for enemy in self
+            .game
+            .world_state
+            .enemies
+            .iter()
+            .filter(|enemy| enemy.loaded)
+        {
+            enemy.draw(&self.assets, alpha);
+        }
+        for pos in self.game.world_state.coins.iter() {
             draw_texture(
-                self.assets.coin.get_at_time((self.time * 1000.0) as u32),
+                self.assets
+                    .coin
+                    .get_at_time((self.game.time * 1000.0) as u32),
                 pos.x,
                 pos.y,
                 WHITE,
             );
-            if self.player.pos.distance_squared(pos) < 64.0 {
-                self.coins += 1;
-                play_sound(
-                    &self.assets.coin_sfx,
-                    PlaySoundParams {
-                        looped: false,
-                        volume: self.actual_volume,
-                    },
-                );
-                false
-            } else {
-                true
-            }
-        });
-        let on_last_level = self.current_level == self.assets.levels.len() - 1;
+        }
+        let on_last_level = self.assets.levels[self.game.level].info.kind == LevelKind::Ending;
         if on_last_level {
             draw_texture(&self.assets.win_screen, 12.0, 12.0, WHITE);
         }
-        self.player.draw(self.assets);
+        self.game.player.draw(&self.assets, alpha);
 
         if !self.in_main_menu && !on_last_level {
             draw_texture(
@@ -233,17 +276,23 @@ impl<'a> Gnobbler<'a> {
                 WHITE,
             );
             self.assets.draw_number(
-                &self.coins.to_string(),
+                &self.game.coins.to_string(),
                 self.camera.target.x - SCREEN_WIDTH / 2.0 + 9.0,
                 self.camera.target.y - SCREEN_HEIGHT / 2.0 + 1.0,
             );
+            // levels with a time limit show the time left instead of the total time
+            let time_limit = self.assets.levels[self.game.level].settings.time_limit;
+            let time = match time_limit {
+                Some(time_limit) => (time_limit - self.game.level_time).max(0.0).ceil(),
+                None => self.game.time,
+            };
             self.assets.draw_number(
-                &format!("{:0>2}", (self.time / 60.0) as u32),
+                &format!("{:0>2}", (time / 60.0) as u32),
                 self.camera.target.x - SCREEN_WIDTH / 2.0 + 32.0,
                 self.camera.target.y - SCREEN_HEIGHT / 2.0 + 1.0,
             );
             self.assets.draw_number(
-                &format!("{:0>2}", (self.time % 60.0) as u32),
+                &format!("{:0>2}", (time % 60.0) as u32),
                 self.camera.target.x - SCREEN_WIDTH / 2.0 + 32.0 + 14.0,
                 self.camera.target.y - SCREEN_HEIGHT / 2.0 + 1.0,
             );
@@ -285,9 +334,10 @@ impl<'a> Gnobbler<'a> {
                 &self.assets.start_btn.frames[1].0,
                 scale_factor,
             );
-            if start_btn.is_hovered() && is_mouse_button_pressed(MouseButton::Left) {
-                self.load_next_level();
-            }
+            let start_pressed =
+                start_btn.is_hovered() && is_mouse_button_pressed(MouseButton::Left);
+            start_btn.draw();
+            let mut volume = self.volume;
             for (m, offset, anim) in [
                 (-1.0, 0.0, &self.assets.minus_btn),
                 (1.0, 84.0, &self.assets.plus_btn),
@@ -300,9 +350,12 @@ impl<'a> Gnobbler<'a> {
                 );
                 btn.draw();
                 if btn.is_hovered() && is_mouse_button_pressed(MouseButton::Left) {
-                    self.set_volume((self.volume + m * 0.1).clamp(0.0, 2.0));
+                    volume = (volume + m * 0.1).clamp(0.0, 2.0);
                 }
             }
+            if volume != self.volume {
+                self.set_volume(volume);
+            }
             let volume_bar_pos = menu_pos + vec2(17.0 * scale_factor, 59.0 * scale_factor);
             let volume_bar_size = vec2(73.0, 9.0);
             draw_rectangle(
@@ -312,16 +365,37 @@ impl<'a> Gnobbler<'a> {
                 volume_bar_size.y * scale_factor,
                 Color::from_hex(0x8e5252),
             );
-            start_btn.draw();
+
+            let packs = &self.assets.packs;
+            if packs.len() > 1 {
+                if is_key_pressed(KeyCode::Left) || is_key_pressed(KeyCode::A) {
+                    self.selected_pack = (self.selected_pack + packs.len() - 1) % packs.len();
+                }
+                if is_key_pressed(KeyCode::Right) || is_key_pressed(KeyCode::D) {
+                    self.selected_pack = (self.selected_pack + 1) % packs.len();
+                }
+                let text = format!("< {} >", packs[self.selected_pack]);
+                let font_size = 8.0 * scale_factor;
+                let size = measure_text(&text, None, font_size as u16, 1.0);
+                draw_text(
+                    &text,
+                    (actual_screen_width - size.width) / 2.0,
+                    menu_pos.y + (menu_size.y + 10.0) * scale_factor,
+                    font_size,
+                    WHITE,
+                );
+            }
+            if start_pressed {
+                self.load_next_level();
+            }
         }
     }
     fn load_next_level(&mut self) {
-        if self.current_level == 0 {
-            self.time = 0.0;
+        if self.assets.levels[self.game.level].info.kind == LevelKind::Menu {
+            self.game.time = 0.0;
+            self.game.coins = 0;
         }
-        self.current_level += 1;
-        (self.world_state, self.player) = self.assets.levels[self.current_level].load_level();
-        self.in_main_menu = false;
+        self.load_level(self.next_level());
     }
 }
 
@@ -334,34 +408,66 @@ fn window_conf() -> Conf {
         ..Default::default()
     }
 }
-#[macroquad::main(window_conf)]
-async fn main() {
-    let assets = Assets::load().await;
+/// Directories to load extra level packs from. These are the user levels directory, if it
+/// exists, and any directories passed with `--levels <dir>`.
+fn user_level_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if cfg!(target_arch = "wasm32") {
+        return dirs;
+    }
+    let user_levels = PathBuf::from(USER_LEVELS_DIR);
+    if user_levels.is_dir() {
+        dirs.push(user_levels);
+    }
+    let mut args = args();
+    while let Some(arg) = args.next() {
+        if arg == "--levels"
+            && let Some(dir) = args.next()
+        {
+            dirs.push(PathBuf::from(dir));
+        }
+    }
+    dirs
+}
+fn main() {
+    if args().any(|arg| arg == "--check-levels") {
+        let passed = lint::check_levels(&user_level_dirs());
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+    let args: Vec<String> = args().collect();
+    if let Some(index) = args.iter().position(|arg| arg == "--render-level") {
+        let (Some(level), Some(output)) = (args.get(index + 1), args.get(index + 2)) else {
+            eprintln!("usage: --render-level <level> <output.png> [--overview]");
+            std::process::exit(2);
+        };
+        let overview = args.iter().any(|arg| arg == "--overview");
+        if let Err(err) = render::render_to_file(level, Path::new(output), overview) {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    macroquad::Window::from_config(window_conf(), run());
+}
+async fn run() {
+    let mut assets = Assets::load().await;
+    for dir in user_level_dirs() {
+        assets.load_level_pack(&dir);
+    }
 
     #[cfg(debug_assertions)]
     let default_volume = 0.0;
     #[cfg(not(debug_assertions))]
     let default_volume = 1.0;
-    play_sound(
-        &assets.song,
-        PlaySoundParams {
-            looped: true,
-            volume: default_volume,
-        },
-    );
-    let mut gnobbler = Gnobbler::new(&assets, default_volume);
+    let mut gnobbler = Gnobbler::new(assets, default_volume);
 
     #[cfg(debug_assertions)]
     {
-        use std::env::args;
-
         if let Some(index) = args().find_map(|f| {
             f.strip_prefix("level=")
                 .and_then(|f| f.parse::<usize>().ok())
         }) {
-            gnobbler.current_level = index;
-            (gnobbler.world_state, gnobbler.player) = assets.levels[index].load_level();
-            gnobbler.in_main_menu = gnobbler.current_level == 0;
+            gnobbler.load_level(index);
         }
     }
     loop {
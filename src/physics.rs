@@ -1,29 +1,28 @@
-use std::collections::HashMap;
-
 use macroquad::prelude::*;
 
-use crate::assets::*;
-
-fn ceil_g(a: f32) -> f32 {
-    if a < 0.0 { a.floor() } else { a.ceil() }
-}
+use crate::{
+    assets::*,
+    levels::TileMarker,
+    tmx::{Tileset, TmxError, TmxErrorKind, tile_id},
+    utils::MAX_VERTICAL_SPEED,
+};
 
-pub fn get_tile(chunks: &HashMap<(i16, i16), Chunk>, x: i16, y: i16) -> i16 {
-    if x < 0 {
-        // make left level boundary act as wall
-        return 50;
-    }
-    let tx = ((x as f32 / 16.0).floor() * 16.0) as i16;
-    let ty = ((y as f32 / 16.0).floor() * 16.0) as i16;
-    if let Some(c) = chunks.get(&(tx, ty)) {
-        c.tile_at((x - tx) as usize, (y - ty) as usize).unwrap_or(0)
-    } else {
-        0
+/// Gets the tile at a position of the collision layer, as its gid including its flip flags and
+/// its behaviour. The sides of the level's bounds are solid walls without a tile, so their gid
+/// is 0. The top of the bounds only stops bodies moving up, so it is left to the physics.
+pub fn get_tile(world: &World, overrides: &TileOverrides, x: i16, y: i16) -> (u32, TileBehaviour) {
+    let left = x as f32 * 8.0;
+    let bounds = world.bounds;
+    if left + 8.0 <= bounds.x || left >= bounds.right() {
+        return (0, TileBehaviour::WALL);
     }
+    let gid = overrides.tile(world.collision(), x, y);
+    (gid, world.tiles.get(gid))
 }
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default, Debug)]
 pub enum TileFlag {
     Collision,
+    #[default]
     NoCollision,
     OneWayCollision,
     Death,
@@ -42,104 +41,477 @@ impl TileFlag {
         matches!(self, TileFlag::NoCollision)
     }
 }
-pub fn get_tile_flag(tile: i16) -> TileFlag {
-    match tile - 1 {
-        16..32 => TileFlag::Death,
-        32..48 => TileFlag::OneWayCollision,
-        48..64 => TileFlag::Collision,
-        _ => TileFlag::NoCollision,
+#[derive(Clone, Copy, Default, Debug)]
+pub struct TileBehaviour {
+    pub flag: TileFlag,
+    pub breakable: bool,
+    /// Upwards speed given to bodies landing on the tile, in pixels per frame at 60 fps
+    pub bounce: Option<f32>,
+    pub marker: Option<TileMarker>,
+}
+impl TileBehaviour {
+    /// How the edges of a level behave
+    pub const WALL: Self = Self {
+        flag: TileFlag::Collision,
+        breakable: false,
+        bounce: None,
+        marker: None,
+    };
+}
+/// Behaviour of every tile in the tileset, read from the tiles' custom properties.
+#[derive(Clone, Default)]
+pub struct TileBehaviours(Vec<TileBehaviour>);
+impl TileBehaviours {
+    pub fn from_tileset(tileset: &Tileset) -> Result<Self, TmxError> {
+        let mut behaviours = vec![TileBehaviour::default(); tileset.tile_count as usize];
+        for tile in tileset.tiles.iter() {
+            let error = |kind| TmxError::new(&tileset.file, None, tile.line, kind);
+            let Some(behaviour) = behaviours.get_mut(tile.id as usize) else {
+                return Err(error(TmxErrorKind::InvalidAttribute(
+                    "id",
+                    tile.id.to_string(),
+                )));
+            };
+            if let Some(collision) = tile.properties.get::<String>("collision").map_err(error)? {
+                behaviour.flag = match collision.as_str() {
+                    "solid" => TileFlag::Collision,
+                    "oneway" => TileFlag::OneWayCollision,
+                    "death" => TileFlag::Death,
+                    "none" => TileFlag::NoCollision,
+                    _ => {
+                        return Err(error(TmxErrorKind::InvalidProperty(
+                            "collision".to_string(),
+                            collision,
+                        )));
+                    }
+                };
+            }
+            behaviour.breakable = tile
+                .properties
+                .get("breakable")
+                .map_err(error)?
+                .unwrap_or(false);
+            behaviour.bounce = tile.properties.get("bounce").map_err(error)?;
+            behaviour.marker = TileMarker::from_properties(&tile.properties).map_err(error)?;
+        }
+        Ok(Self(behaviours))
+    }
+    /// Gets the behaviour of a tile as it is stored in a layer, where 0 is an empty tile.
+    /// Flipped tiles behave the same as unflipped ones.
+    pub fn get(&self, gid: u32) -> TileBehaviour {
+        let id = tile_id(gid);
+        if id == 0 {
+            return TileBehaviour::default();
+        }
+        self.0.get(id as usize - 1).copied().unwrap_or_default()
+    }
+}
+/// The part of a body that collides, relative to the body's position
+#[derive(Clone, Copy, Debug)]
+pub struct Hitbox {
+    pub offset: Vec2,
+    pub size: Vec2,
+}
+impl Hitbox {
+    /// A box the size of a tile, which the player and enemies use
+    pub const TILE: Self = Self {
+        offset: Vec2::ZERO,
+        size: Vec2::splat(8.0),
+    };
+    /// The box of a body at a position
+    pub fn at(&self, pos: Vec2) -> Rect {
+        Rect::new(
+            pos.x + self.offset.x,
+            pos.y + self.offset.y,
+            self.size.x,
+            self.size.y,
+        )
+    }
+}
+
+/// The tiles a span of pixels overlaps
+pub fn tile_span(start: f32, length: f32) -> std::ops::RangeInclusive<i16> {
+    (start / 8.0).floor() as i16..=((start + length) / 8.0).ceil() as i16 - 1
+}
+
+/// Whether a box overlaps a tile enough to touch what's in it, like spikes. The middle of
+/// the box, shrunk by up to half a tile on each side, has to come within half a tile of the
+/// tile's center, so bodies can brush past the edges of tiles.
+fn touches_tile(hitbox: Rect, tx: i16, ty: i16) -> bool {
+    let inset = (hitbox.size() / 2.0).min(Vec2::splat(4.0));
+    let center = (vec2(tx as f32, ty as f32) + 0.5) * 8.0;
+    let closest = center.clamp(
+        hitbox.point() + inset,
+        hitbox.point() + hitbox.size() - inset,
+    );
+    closest.distance_squared(center) < 16.0
+}
+
+/// How a body touched a tile
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ContactKind {
+    /// The body landed on the tile
+    Floor,
+    /// The body bumped its head on the tile
+    Ceiling,
+    /// The body ran into the tile while moving left
+    LeftWall,
+    /// The body ran into the tile while moving right
+    RightWall,
+    /// The body overlaps a tile that doesn't stop it, like spikes
+    Overlap,
+    /// The body was launched upwards by the tile
+    Bounce,
+}
+#[derive(Clone, Copy, Debug)]
+pub struct Contact {
+    pub kind: ContactKind,
+    /// Position of the tile
+    pub tile: (i16, i16),
+    /// The tile's gid, including its flip flags. 0 for the walls at the edges of the level.
+    pub gid: u32,
+    pub behaviour: TileBehaviour,
+}
+/// Where a body ended up after moving, and every tile it touched on the way.
+#[derive(Clone, Debug)]
+pub struct PhysicsResult {
+    pub pos: Vec2,
+    pub contacts: Vec<Contact>,
+}
+impl PhysicsResult {
+    /// Records a contact, unless the body already touched the same tile the same way
+    fn add(&mut self, contact: Contact) {
+        if !self
+            .contacts
+            .iter()
+            .any(|other| other.kind == contact.kind && other.tile == contact.tile)
+        {
+            self.contacts.push(contact);
+        }
+    }
+    pub fn touched(&self, kind: ContactKind) -> impl Iterator<Item = &Contact> {
+        self.contacts
+            .iter()
+            .filter(move |contact| contact.kind == kind)
+    }
+    pub fn grounded(&self) -> bool {
+        self.touched(ContactKind::Floor).next().is_some()
+    }
+    pub fn hit_wall(&self) -> bool {
+        self.touched(ContactKind::LeftWall)
+            .chain(self.touched(ContactKind::RightWall))
+            .next()
+            .is_some()
+    }
+    pub fn touched_death_tile(&self) -> bool {
+        self.touched(ContactKind::Overlap)
+            .any(|contact| contact.behaviour.flag.is_death())
+    }
+    pub fn jumped_on_trampoline(&self) -> bool {
+        self.touched(ContactKind::Bounce).next().is_some()
     }
 }
+
+/// Moves a body and resolves its collisions with the collision layer.
+/// Fast bodies are moved in steps of at most a tile, so they can't pass through thin walls
+/// or platforms.
 pub fn update_physicsbody(
     pos: Vec2,
+    hitbox: Hitbox,
     velocity: &mut Vec2,
     delta_time: f32,
     world: &World,
-    broken_tiles: &[(i16, i16)],
-) -> (Vec2, bool, bool, Option<(i16, i16)>, bool) {
-    let mut new = pos + *velocity * delta_time;
-    if new.y - 8.0 > pos.y {
-        new.y = pos.y + 8.0;
+    overrides: &TileOverrides,
+) -> PhysicsResult {
+    let mut result = PhysicsResult {
+        pos,
+        contacts: Vec::new(),
+    };
+    let mut remaining = delta_time;
+    while remaining > 0.0 {
+        velocity.y = velocity.y.clamp(-MAX_VERTICAL_SPEED, MAX_VERTICAL_SPEED);
+        // the velocity can change between steps, like when landing on a trampoline
+        let speed = velocity.abs().max_element();
+        let step_time = if speed * remaining > 8.0 {
+            8.0 / speed
+        } else {
+            remaining
+        };
+        remaining -= step_time;
+        step_physicsbody(&mut result, hitbox, velocity, step_time, world, overrides);
     }
-    if new.y + 8.0 < pos.y {
-        new.y = pos.y - 8.0;
-    }
-    let mut touched_death_tile = false;
-    let mut jumped_on_trampoline = false;
-    let mut broke_block = None;
+    result
+}
+
+/// Moves a body by at most a tile
+fn step_physicsbody(
+    result: &mut PhysicsResult,
+    hitbox: Hitbox,
+    velocity: &mut Vec2,
+    delta_time: f32,
+    world: &World,
+    overrides: &TileOverrides,
+) {
+    let pos = result.pos;
+    let mut new = pos + *velocity * delta_time;
     let original_velocity = *velocity;
+    let old = hitbox.at(pos);
+    let contact = |kind, tx, ty| {
+        let (gid, behaviour) = get_tile(world, overrides, tx, ty);
+        Contact {
+            kind,
+            tile: (tx, ty),
+            gid,
+            behaviour,
+        }
+    };
 
-    let tile_x = pos.x / 8.0;
-    let tile_y = pos.y / 8.0;
-
-    let tiles_y = [
-        (tile_x.trunc(), ceil_g(new.y / 8.0)),
-        (ceil_g(tile_x), ceil_g(new.y / 8.0)),
-        (tile_x.trunc(), (new.y / 8.0).trunc()),
-        (ceil_g(tile_x), (new.y / 8.0).trunc()),
-    ];
-
-    let mut grounded = false;
-    for (tx, ty) in tiles_y.into_iter() {
-        let tile = get_tile(&world.collision, tx as i16, ty as i16);
-        let flag = get_tile_flag(tile);
-        if (flag.is_collision() && !(tile == 49 && broken_tiles.contains(&(tx as i16, ty as i16))))
-            || (velocity.y > 0.0 && ty.trunc() > tile_y.trunc() && flag.is_one_way())
-        {
-            let c = if velocity.y < 0.0 {
-                if tile == 49 && broke_block.is_none() {
-                    broke_block = Some((tx as i16, ty as i16));
-                }
-                tile_y.floor() * 8.0
+    // tiles the box overlaps after moving vertically, bottom row first.
+    // the first row with a tile that stops the body is where it stops
+    let moved = hitbox.at(vec2(pos.x, new.y));
+    'vertical: for ty in tile_span(moved.y, moved.h).rev() {
+        let mut stopped = false;
+        // the top of the bounds is a ceiling, but bodies above it can still move sideways
+        let above_bounds = velocity.y < 0.0 && ty as f32 * 8.0 + 8.0 <= world.bounds.y;
+        for tx in tile_span(old.x, old.w) {
+            let (gid, behaviour) = if above_bounds {
+                (0, TileBehaviour::WALL)
+            } else {
+                get_tile(world, overrides, tx, ty)
+            };
+            let flag = behaviour.flag;
+            // one way tiles only catch bodies that were above them
+            if flag.is_collision()
+                || (velocity.y > 0.0 && ty as f32 * 8.0 >= old.bottom() && flag.is_one_way())
+            {
+                let kind = if velocity.y < 0.0 {
+                    ContactKind::Ceiling
+                } else {
+                    ContactKind::Floor
+                };
+                result.add(Contact {
+                    kind,
+                    tile: (tx, ty),
+                    gid,
+                    behaviour,
+                });
+                stopped = true;
+            }
+        }
+        if stopped {
+            new.y = if velocity.y < 0.0 {
+                (old.y / 8.0).floor() * 8.0 - hitbox.offset.y
             } else {
-                grounded = true;
-                tile_y.ceil() * 8.0
+                (old.bottom() / 8.0).ceil() * 8.0 - hitbox.size.y - hitbox.offset.y
             };
-            new.y = c;
             velocity.y = 0.0;
-            break;
+            break 'vertical;
         }
     }
-    let tiles_x = [
-        ((new.x / 8.0).trunc(), ceil_g(new.y / 8.0)),
-        (ceil_g(new.x / 8.0), ceil_g(new.y / 8.0)),
-        (ceil_g(new.x / 8.0), (new.y / 8.0).trunc()),
-        ((new.x / 8.0).trunc(), (new.y / 8.0).trunc()),
-    ];
-
-    for (tx, ty) in tiles_x {
-        let tile = get_tile(&world.collision, tx as i16, ty as i16);
-        let flag = get_tile_flag(tile);
-        if !touched_death_tile
-            && tx > 0.0
-            && (new + vec2(4.0, 0.0)).distance_squared(vec2(tx + 0.5, ty) * 8.0) < 16.0
-        {
-            touched_death_tile = flag.is_death();
-        }
-        if !jumped_on_trampoline
-            && tile == 113
-            && original_velocity.y > 0.0
-            && (new + vec2(4.0, 0.0)).distance_squared(vec2(tx + 0.5, ty) * 8.0) < 16.0
-        {
-            velocity.y = -4.5 * 60.0;
-            jumped_on_trampoline = true;
+
+    let moved = hitbox.at(new);
+    let mut stopped = false;
+    for ty in tile_span(moved.y, moved.h).rev() {
+        for tx in tile_span(moved.x, moved.w) {
+            let contact = contact(ContactKind::Overlap, tx, ty);
+            let behaviour = contact.behaviour;
+            if behaviour.flag.is_collision() {
+                let kind = if velocity.x < 0.0 {
+                    ContactKind::LeftWall
+                } else {
+                    ContactKind::RightWall
+                };
+                result.add(Contact { kind, ..contact });
+                stopped = true;
+            } else if contact.gid != 0 && touches_tile(moved, tx, ty) {
+                result.add(contact);
+                if let Some(bounce) = behaviour.bounce
+                    && original_velocity.y > 0.0
+                    && !result.jumped_on_trampoline()
+                {
+                    velocity.y = -bounce * 60.0;
+                    result.add(Contact {
+                        kind: ContactKind::Bounce,
+                        ..contact
+                    });
+                }
+            }
         }
-        if flag.is_collision() && !(tile == 49 && broken_tiles.contains(&(tx as i16, ty as i16))) {
-            let c = if velocity.x < 0.0 {
-                tile_x.floor() * 8.0
-            } else {
-                tile_x.ceil() * 8.0
-            };
-            new.x = c;
-            velocity.x = 0.0;
-            break;
+    }
+    if stopped {
+        new.x = if velocity.x < 0.0 {
+            (old.x / 8.0).floor() * 8.0 - hitbox.offset.x
+        } else {
+            (old.right() / 8.0).ceil() * 8.0 - hitbox.size.x - hitbox.offset.x
+        };
+        velocity.x = 0.0;
+    }
+    result.pos = new;
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::tmx::parse_tileset;
+
+    /// Builds a level from rows of tiles, where `#` is solid and `-` is a one way platform
+    pub(crate) fn world(rows: &[&str]) -> World {
+        let tiles = parse_tileset("tileset.tsx", BUILTIN_TILESET)
+            .and_then(|tileset| TileBehaviours::from_tileset(&tileset))
+            .unwrap();
+        let (width, height) = (rows[0].len(), rows.len());
+        let csv = rows
+            .iter()
+            .flat_map(|row| row.chars())
+            .map(|tile| match tile {
+                '#' => "50",
+                '-' => "33",
+                _ => "0",
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let xml = format!(
+            r#"<map width="{width}" height="{height}" tilewidth="8" tileheight="8" infinite="0">
+                <tileset firstgid="1" source="tileset.tsx"/>
+                <layer name="collision" width="{width}" height="{height}">
+                    <data encoding="csv">{csv}</data>
+                </layer>
+            </map>"#
+        );
+        World::from_data("test.tmx", &xml, &tiles).unwrap()
+    }
+
+    /// A tall level with a single row of tiles in the middle
+    fn floor(tile: &str) -> World {
+        let mut rows = vec![".........."; 40];
+        rows[30] = tile;
+        world(&rows)
+    }
+
+    fn move_body(
+        world: &World,
+        pos: Vec2,
+        hitbox: Hitbox,
+        mut velocity: Vec2,
+        delta_time: f32,
+    ) -> (Vec2, Vec2, bool) {
+        let result = update_physicsbody(
+            pos,
+            hitbox,
+            &mut velocity,
+            delta_time,
+            world,
+            &TileOverrides::default(),
+        );
+        (result.pos, velocity, result.grounded())
+    }
+
+    #[test]
+    fn fast_horizontal_moves_stop_at_thin_walls() {
+        let world = world(&["..........", "....#.....", "##########"]);
+        let (pos, velocity, _) = move_body(
+            &world,
+            vec2(8.0, 8.0),
+            Hitbox::TILE,
+            vec2(6000.0, 0.0),
+            1.0 / 60.0,
+        );
+        assert_eq!(pos, vec2(24.0, 8.0));
+        assert_eq!(velocity.x, 0.0);
+
+        let (pos, velocity, _) = move_body(
+            &world,
+            vec2(64.0, 8.0),
+            Hitbox::TILE,
+            vec2(-6000.0, 0.0),
+            1.0 / 60.0,
+        );
+        assert_eq!(pos, vec2(40.0, 8.0));
+        assert_eq!(velocity.x, 0.0);
+    }
+
+    #[test]
+    fn small_hitboxes_stop_at_thin_walls() {
+        let world = world(&["..........", "....#.....", "##########"]);
+        let hitbox = Hitbox {
+            offset: vec2(3.0, 3.0),
+            size: vec2(2.0, 2.0),
+        };
+        let (pos, velocity, _) = move_body(
+            &world,
+            vec2(0.0, 8.0),
+            hitbox,
+            vec2(6000.0, 0.0),
+            1.0 / 60.0,
+        );
+        assert_eq!(pos, vec2(32.0 - 5.0, 8.0));
+        assert_eq!(velocity.x, 0.0);
+    }
+
+    #[test]
+    fn fast_falls_land_on_thin_floors() {
+        for tile in ["##########", "----------"] {
+            let world = floor(tile);
+            let (pos, velocity, grounded) = move_body(
+                &world,
+                vec2(8.0, 0.0),
+                Hitbox::TILE,
+                vec2(0.0, MAX_VERTICAL_SPEED),
+                0.5,
+            );
+            assert_eq!(pos, vec2(8.0, 30.0 * 8.0 - 8.0), "landing on {tile}");
+            assert_eq!(velocity.y, 0.0);
+            assert!(grounded);
         }
     }
-    (
-        new,
-        grounded,
-        touched_death_tile,
-        broke_block,
-        jumped_on_trampoline,
-    )
+
+    #[test]
+    fn fast_rises_stop_at_thin_ceilings() {
+        let world = floor("##########");
+        let (pos, velocity, grounded) = move_body(
+            &world,
+            vec2(8.0, 32.0 * 8.0),
+            Hitbox::TILE,
+            vec2(0.0, -MAX_VERTICAL_SPEED),
+            0.25,
+        );
+        assert_eq!(pos, vec2(8.0, 31.0 * 8.0));
+        assert_eq!(velocity.y, 0.0);
+        assert!(!grounded);
+    }
+
+    #[test]
+    fn fast_rises_pass_through_one_way_platforms() {
+        let world = floor("----------");
+        let (pos, velocity, _) = move_body(
+            &world,
+            vec2(8.0, 32.0 * 8.0),
+            Hitbox::TILE,
+            vec2(0.0, -MAX_VERTICAL_SPEED),
+            0.25,
+        );
+        assert_eq!(pos, vec2(8.0, 32.0 * 8.0 - MAX_VERTICAL_SPEED * 0.25));
+        assert_eq!(velocity.y, -MAX_VERTICAL_SPEED);
+    }
+
+    #[test]
+    fn bodies_above_the_bounds_move_sideways() {
+        let world = world(&["#.........", "##########"]);
+        assert_eq!(world.bounds.y, 0.0);
+        let (pos, velocity, _) =
+            move_body(&world, vec2(8.0, -8.0), Hitbox::TILE, vec2(60.0, 0.0), 0.5);
+        assert_eq!(pos, vec2(38.0, -8.0));
+        assert_eq!(velocity.x, 60.0);
+
+        // the top of the bounds still stops bodies moving up into it
+        let (pos, velocity, _) = move_body(
+            &world,
+            vec2(16.0, 8.0),
+            Hitbox::TILE,
+            vec2(0.0, -MAX_VERTICAL_SPEED),
+            0.25,
+        );
+        assert_eq!(pos, vec2(16.0, 0.0));
+        assert_eq!(velocity.y, 0.0);
+    }
 }
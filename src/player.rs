@@ -1,8 +1,8 @@
 use macroquad::prelude::*;
 
 use crate::{
-    assets::{Assets, WorldState},
-    physics::update_physicsbody,
+    assets::{Assets, World, WorldState},
+    physics::{ContactKind, Hitbox, PhysicsResult, update_physicsbody},
     utils::*,
 };
 
@@ -27,9 +27,38 @@ pub enum PlayerUpdateResult {
     NextLevel,
 }
 
+/// The keys the player is pressing, read once per frame and used by every step in the frame
+#[derive(Clone, Copy, Default)]
+pub struct PlayerInput {
+    pub axis: Vec2,
+    pub jump_held: bool,
+    /// Whether jump was pressed since the last step, so a press between steps isn't missed
+    pub jump_pressed: bool,
+    pub noclip: bool,
+}
+impl PlayerInput {
+    pub fn poll(&mut self) {
+        self.axis = get_input_axis();
+        self.jump_held = is_key_down(KeyCode::Space);
+        self.jump_pressed |= is_key_pressed(KeyCode::Space);
+        #[cfg(debug_assertions)]
+        {
+            self.noclip = is_key_down(KeyCode::LeftShift);
+        }
+    }
+}
+
+/// Seconds the player's death animation lasts, which is how long dying takes
+pub fn death_time(assets: &Assets) -> f32 {
+    assets.player.animations[AnimState::Die as usize].total_length as f32 / 1000.0
+}
+
 pub struct Player {
     pub pos: Vec2,
     pub camera_pos: Vec2,
+    /// Positions before the last step, which drawing interpolates from
+    pub prev_pos: Vec2,
+    pub prev_camera_pos: Vec2,
 
     pub velocity: Vec2,
     pub grounded: bool,
@@ -38,20 +67,25 @@ pub struct Player {
     pub time: f32,
     pub anim_state: AnimState,
     pub facing_left: bool,
+    pub hitbox: Hitbox,
 
     pub player_state: PlayerState,
 }
 impl Player {
     pub fn new(pos: Vec2) -> Self {
+        let camera_pos = pos + vec2(SCREEN_WIDTH / 2.0, -24.0);
         Self {
             pos,
-            camera_pos: pos + vec2(SCREEN_WIDTH / 2.0, -24.0),
+            camera_pos,
+            prev_pos: pos,
+            prev_camera_pos: camera_pos,
             time: 0.0,
             anim_state: AnimState::Idle,
             grounded: true,
             velocity: Vec2::ZERO,
             jump_frames: 0.0,
             facing_left: false,
+            hitbox: Hitbox::TILE,
             player_state: PlayerState::Active,
         }
     }
@@ -66,19 +100,19 @@ impl Player {
     pub fn update(
         &mut self,
         delta_time: f32,
-        assets: &Assets,
+        input: &PlayerInput,
+        world: &World,
         world_state: &mut WorldState,
-        current_level: usize,
+        death_time: f32,
     ) -> PlayerUpdateResult {
+        let (jump_held, jump_pressed) = (input.jump_held, input.jump_pressed);
+        self.prev_pos = self.pos;
+        self.prev_camera_pos = self.camera_pos;
         self.time += delta_time;
         match self.player_state {
             PlayerState::Active => {
-                let input = get_input_axis();
-
-                #[cfg(debug_assertions)]
-                let noclip = is_key_down(KeyCode::LeftShift);
-                #[cfg(not(debug_assertions))]
-                let noclip = false;
+                let noclip = input.noclip;
+                let input = input.axis;
 
                 let friction_mod;
                 if noclip {
@@ -101,17 +135,17 @@ impl Player {
                     }
                 }
 
-                if self.pos.x >= (assets.levels[current_level].finish_pos.0 * 8) as f32 {
+                if self.hitbox.at(self.pos).overlaps(&world.finish_area) {
                     self.player_state = PlayerState::Won;
                 }
 
                 if self.grounded {
                     self.jump_frames = 0.0;
                 }
-                if is_key_down(KeyCode::Space)
+                if jump_held
                     && (self.grounded || (self.jump_frames > 0.0 && self.jump_frames < 0.5))
                 {
-                    if self.jump_frames == 0.0 && is_key_pressed(KeyCode::Space) {
+                    if self.jump_frames == 0.0 && jump_pressed {
                         self.velocity.y -= 2.3 * 60.0;
                     } else {
                         self.velocity.y -= 30.0 * 10.0 * delta_time;
@@ -127,32 +161,28 @@ impl Player {
                     }
                     * delta_time;
 
-                let touched_death_tile;
-                let broke_block;
-                let jumped_on_trampoline;
-                if !noclip {
-                    self.velocity.y += GRAVITY * delta_time;
-                    (
-                        self.pos,
-                        self.grounded,
-                        touched_death_tile,
-                        broke_block,
-                        jumped_on_trampoline,
-                    ) = update_physicsbody(
+                let physics = if !noclip {
+                    self.velocity.y += GRAVITY * world.settings.gravity_scale * delta_time;
+                    let physics = update_physicsbody(
                         self.pos,
+                        self.hitbox,
                         &mut self.velocity,
                         delta_time,
-                        &assets.levels[current_level],
-                        &world_state.broken_tiles,
+                        world,
+                        &world_state.tile_overrides,
                     );
+                    self.pos = physics.pos;
+                    self.grounded = physics.grounded();
+                    physics
                 } else {
-                    touched_death_tile = false;
-                    jumped_on_trampoline = false;
-                    broke_block = None;
                     self.pos += self.velocity * delta_time;
-                }
+                    PhysicsResult {
+                        pos: self.pos,
+                        contacts: Vec::new(),
+                    }
+                };
 
-                self.camera_pos.x = self.pos.x.max(SCREEN_WIDTH / 2.0);
+                self.camera_pos.x = self.pos.x;
                 let target = self.pos.y - 24.0;
                 if self.camera_pos.y < target {
                     self.camera_pos.y = target;
@@ -164,13 +194,18 @@ impl Player {
                             max_delta * if delta < 0.0 { -1.0 } else { 1.0 } + target;
                     }
                 }
-                if touched_death_tile {
+                self.camera_pos = world.clamp_camera(self.camera_pos);
+                // falling out of the bottom of the level
+                if physics.touched_death_tile() || (!noclip && self.pos.y > world.bounds.bottom()) {
                     self.die();
                     PlayerUpdateResult::PlayStompSfx
-                } else if let Some(broke_block) = broke_block {
-                    world_state.broken_tiles.push(broke_block);
+                } else if let Some(block) = physics
+                    .touched(ContactKind::Ceiling)
+                    .find(|contact| contact.behaviour.breakable)
+                {
+                    world_state.tile_overrides.clear(block.tile.0, block.tile.1);
                     PlayerUpdateResult::PlayStompSfx
-                } else if jumped_on_trampoline {
+                } else if physics.jumped_on_trampoline() {
                     PlayerUpdateResult::PlayTrampolineSfx
                 } else {
                     PlayerUpdateResult::None
@@ -180,9 +215,7 @@ impl Player {
                 self.velocity = self.velocity.lerp(vec2(0.0, -32.0), delta_time * 8.0);
                 self.pos += self.velocity * delta_time;
                 self.anim_state = AnimState::Die;
-                if self.time * 1000.0
-                    >= assets.player.animations[self.anim_state as usize].total_length as f32
-                {
+                if self.time >= death_time {
                     // temporary, just to hide player off screen
                     PlayerUpdateResult::RestartLevel
                 } else {
@@ -190,7 +223,10 @@ impl Player {
                 }
             }
             PlayerState::Won => {
-                let target = assets.levels[current_level].finish_pos;
+                // levels without a dock end as soon as the finish is reached
+                let Some(target) = world.finish_pos else {
+                    return PlayerUpdateResult::NextLevel;
+                };
                 let target = vec2(target.0 as f32 + 2.5, target.1 as f32 - 0.2) * 8.0;
                 self.anim_state = AnimState::Walk;
                 self.pos = self.pos.move_towards(target, delta_time * 16.0);
@@ -219,12 +255,15 @@ impl Player {
             }
         }
     }
-    pub fn draw(&mut self, assets: &Assets) {
+    /// Draws the player `alpha` of the way from its position before the last step to its
+    /// current one
+    pub fn draw(&mut self, assets: &Assets, alpha: f32) {
+        let pos = self.prev_pos.lerp(self.pos, alpha).floor();
         draw_texture_ex(
             assets.player.animations[self.anim_state as usize]
                 .get_at_time((self.time * 1000.0) as u32),
-            self.pos.x.floor(),
-            self.pos.y.floor(),
+            pos.x,
+            pos.y,
             WHITE,
             DrawTextureParams {
                 flip_x: self.facing_left,
@@ -0,0 +1,214 @@
+//! Renders levels to images on the CPU, for `--render-level`. Runs without a window, so it
+//! works in CI and on machines without a GPU.
+
+use std::path::Path;
+
+use asefile::AsepriteFile;
+use image::{
+    Pixel, Rgba, RgbaImage,
+    imageops::{self, FilterType},
+};
+use macroquad::prelude::*;
+use num_traits::ToPrimitive;
+
+use crate::{
+    assets::*,
+    levels::{LevelKind, parse_manifest},
+    physics::TileBehaviours,
+    player::AnimState,
+    tmx::{
+        FLIPPED_DIAGONALLY, FLIPPED_HORIZONTALLY, FLIPPED_VERTICALLY, decode_maps, parse_tileset,
+        tile_id,
+    },
+};
+
+/// How much smaller overview images are than the level
+const OVERVIEW_SCALE: u32 = 4;
+
+/// The sprites a level is drawn with, decoded from the same files the game uses.
+struct Sprites {
+    tileset: RgbaImage,
+    coin: RgbaImage,
+    boat: RgbaImage,
+    /// First frame of every enemy's animation
+    enemies: Vec<RgbaImage>,
+    player: RgbaImage,
+}
+impl Sprites {
+    fn load() -> Self {
+        let enemies = AsepriteFile::read(&include_bytes!("../assets/enemies.ase")[..]).unwrap();
+        Self {
+            tileset: ase_frame(include_bytes!("../assets/tileset.ase"), 0),
+            coin: ase_frame(include_bytes!("../assets/coin.ase"), 0),
+            boat: ase_frame(include_bytes!("../assets/boat.ase"), 0),
+            enemies: (0..enemies.num_tags())
+                .map(|tag| {
+                    let tag = enemies.get_tag(tag).unwrap();
+                    enemies.frame(tag.from_frame()).image()
+                })
+                .collect(),
+            player: {
+                let player =
+                    AsepriteFile::read(&include_bytes!("../assets/player.ase")[..]).unwrap();
+                let tag = player.get_tag(AnimState::Idle as u32).unwrap();
+                player.frame(tag.from_frame()).image()
+            },
+        }
+    }
+}
+
+fn ase_frame(bytes: &[u8], frame: u32) -> RgbaImage {
+    AsepriteFile::read(bytes).unwrap().frame(frame).image()
+}
+
+/// Renders a level to a png. `level` is the file name of a built in level, or the path of a
+/// `.tmx` file.
+pub fn render_to_file(level: &str, output: &Path, overview: bool) -> Result<(), String> {
+    let tileset = parse_tileset("tileset.tsx", BUILTIN_TILESET).map_err(|err| err.to_string())?;
+    let tiles = TileBehaviours::from_tileset(&tileset).map_err(|err| err.to_string())?;
+    let tile_animations = TileAnimations::from_tileset(&tileset).map_err(|err| err.to_string())?;
+
+    let maps = decode_maps("levels.bin", BUILTIN_LEVELS).map_err(|err| err.to_string())?;
+    let world = match maps.iter().find(|map| map.file == level) {
+        Some(map) => {
+            let mut world = World::from_map(map, &tiles).map_err(|err| err.to_string())?;
+            let manifest =
+                parse_manifest("levels.xml", BUILTIN_MANIFEST).map_err(|err| err.to_string())?;
+            if let Some(info) = manifest.into_iter().find(|info| info.file == level) {
+                world.set_info(info);
+            }
+            world
+        }
+        None => {
+            let xml = std::fs::read_to_string(level).map_err(|err| format!("{level}: {err}"))?;
+            World::from_data(level, &xml, &tiles).map_err(|err| err.to_string())?
+        }
+    };
+
+    let mut image = render_level(&world, &Sprites::load(), &tile_animations);
+    if overview {
+        let (width, height) = (
+            (image.width() / OVERVIEW_SCALE).max(1),
+            (image.height() / OVERVIEW_SCALE).max(1),
+        );
+        image = imageops::resize(&image, width, height, FilterType::Triangle);
+    }
+    image
+        .save(output)
+        .map_err(|err| format!("{}: {err}", output.display()))
+}
+
+/// Draws everything the game draws for a level when it starts, lined up as if the camera
+/// was centered on the parallax origin.
+fn render_level(world: &World, sprites: &Sprites, tile_animations: &TileAnimations) -> RgbaImage {
+    let (world_state, player) = world.load_level();
+
+    let mut tiles = Vec::new();
+    for layer in world.layers.iter() {
+        let origin = layer.origin(world.parallax_origin, world.parallax_origin);
+        for (x, y, gid) in layer.tiles.iter() {
+            let pos = origin + vec2(x as f32, y as f32) * 8.0;
+            tiles.push((pos, tile_animations.frame(gid, 0), layer.opacity));
+        }
+    }
+    // sprites are drawn in the same order as in the game
+    let mut sprites_at: Vec<_> = world
+        .finish_pos
+        .map(|finish| {
+            (
+                vec2((finish.0 * 8) as f32 + 8.0, (finish.1 * 8) as f32 - 32.0),
+                &sprites.boat,
+                false,
+            )
+        })
+        .into_iter()
+        .collect();
+    for enemy in world_state.enemies.iter() {
+        let sprite = &sprites.enemies[enemy.ty.to_usize().unwrap()];
+        sprites_at.push((
+            enemy.pos.floor() - vec2(4.0, 8.0),
+            sprite,
+            enemy.facing_left,
+        ));
+    }
+    for coin in world_state.coins.iter() {
+        sprites_at.push((*coin, &sprites.coin, false));
+    }
+    // menus hide the player
+    if world.info.kind != LevelKind::Menu {
+        sprites_at.push((player.pos.floor(), &sprites.player, false));
+    }
+
+    // the image covers everything that was drawn
+    let mut min = vec2(f32::MAX, f32::MAX);
+    let mut max = vec2(f32::MIN, f32::MIN);
+    for (pos, _, _) in tiles.iter() {
+        min = min.min(*pos);
+        max = max.max(*pos + 8.0);
+    }
+    for (pos, sprite, _) in sprites_at.iter() {
+        min = min.min(*pos);
+        max = max.max(*pos + vec2(sprite.width() as f32, sprite.height() as f32));
+    }
+    let (min, max) = (min.floor(), max.ceil());
+    let size = (max - min).max(Vec2::ONE);
+
+    let mut image = RgbaImage::from_pixel(
+        size.x as u32,
+        size.y as u32,
+        Rgba(world.settings.background_color),
+    );
+    for (pos, gid, opacity) in tiles {
+        draw_tile(&mut image, &sprites.tileset, pos - min, gid, opacity);
+    }
+    for (pos, sprite, flip_x) in sprites_at {
+        for (x, y, pixel) in sprite.enumerate_pixels() {
+            let x = if flip_x { sprite.width() - 1 - x } else { x };
+            blend(
+                &mut image,
+                pos - min + vec2(x as f32, y as f32),
+                *pixel,
+                1.0,
+            );
+        }
+    }
+    image
+}
+
+/// Draws a tile from the tileset, flipped like [`Spritesheet::draw_gid`] does
+fn draw_tile(image: &mut RgbaImage, tileset: &RgbaImage, pos: Vec2, gid: u32, opacity: f32) {
+    let Some(index) = tile_id(gid).checked_sub(1) else {
+        return;
+    };
+    let columns = tileset.width() / 8;
+    let (tile_x, tile_y) = ((index % columns) * 8, (index / columns) * 8);
+    for y in 0..8 {
+        for x in 0..8 {
+            // undo the flips in the opposite order tiled applies them in
+            let (mut sx, mut sy) = (x, y);
+            if gid & FLIPPED_VERTICALLY != 0 {
+                sy = 7 - sy;
+            }
+            if gid & FLIPPED_HORIZONTALLY != 0 {
+                sx = 7 - sx;
+            }
+            if gid & FLIPPED_DIAGONALLY != 0 {
+                (sx, sy) = (sy, sx);
+            }
+            let Some(pixel) = tileset.get_pixel_checked(tile_x + sx, tile_y + sy) else {
+                continue;
+            };
+            blend(image, pos + vec2(x as f32, y as f32), *pixel, opacity);
+        }
+    }
+}
+
+fn blend(image: &mut RgbaImage, pos: Vec2, mut pixel: Rgba<u8>, opacity: f32) {
+    if pos.x < 0.0 || pos.y < 0.0 {
+        return;
+    }
+    pixel.0[3] = (pixel.0[3] as f32 * opacity) as u8;
+    if let Some(target) = image.get_pixel_mut_checked(pos.x as u32, pos.y as u32) {
+        target.blend(&pixel);
+    }
+}
@@ -0,0 +1,243 @@
+//! Caches the tile layers of the current level as one texture per chunk, so drawing a level
+//! takes a few draw calls per visible chunk, however long the level is.
+
+use macroquad::prelude::*;
+
+use crate::{assets::*, utils::*};
+
+/// Width and height of a chunk, in tiles
+const CHUNK_TILES: i32 = 16;
+const CHUNK_SIZE: f32 = CHUNK_TILES as f32 * 8.0;
+
+/// A chunk of a layer, rendered ahead of time
+struct CachedChunk {
+    /// Every tile that isn't animated. Empty chunks don't get a texture.
+    target: Option<RenderTarget>,
+    /// Animated tiles, which are drawn over the texture every frame
+    animated: Vec<(i16, i16, u32)>,
+}
+
+/// The chunks covering one layer's grid. Chunks are rendered the first time they're seen.
+struct LayerCache {
+    /// Chunk coordinates of the top left chunk
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    chunks: Vec<Option<CachedChunk>>,
+}
+impl LayerCache {
+    fn new(tiles: &TileGrid) -> Self {
+        let (x, y) = (
+            (tiles.x as i32).div_euclid(CHUNK_TILES),
+            (tiles.y as i32).div_euclid(CHUNK_TILES),
+        );
+        let (width, height) = if tiles.width == 0 {
+            (0, 0)
+        } else {
+            (
+                (tiles.x as i32 + tiles.width as i32 - 1).div_euclid(CHUNK_TILES) - x + 1,
+                (tiles.y as i32 + tiles.height as i32 - 1).div_euclid(CHUNK_TILES) - y + 1,
+            )
+        };
+        Self {
+            x,
+            y,
+            width,
+            height,
+            chunks: (0..width * height).map(|_| None).collect(),
+        }
+    }
+    fn index(&self, cx: i32, cy: i32) -> Option<usize> {
+        let (x, y) = (cx - self.x, cy - self.y);
+        ((0..self.width).contains(&x) && (0..self.height).contains(&y))
+            .then_some((x + y * self.width) as usize)
+    }
+    /// Forgets the chunk holding a tile, so it's rendered again
+    fn invalidate(&mut self, x: i16, y: i16) {
+        let (cx, cy) = (
+            (x as i32).div_euclid(CHUNK_TILES),
+            (y as i32).div_euclid(CHUNK_TILES),
+        );
+        if let Some(index) = self.index(cx, cy) {
+            self.chunks[index] = None;
+        }
+    }
+}
+
+pub struct TileCache {
+    /// Index of the level the cache holds
+    level: Option<usize>,
+    layers: Vec<LayerCache>,
+    /// The tile overrides the cached chunks of the collision layer were rendered with
+    tile_overrides: TileOverrides,
+}
+impl TileCache {
+    pub fn new() -> Self {
+        Self {
+            level: None,
+            layers: Vec::new(),
+            tile_overrides: TileOverrides::default(),
+        }
+    }
+    /// Drops every cached chunk, for when a level or the tileset changed
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    pub fn clear(&mut self) {
+        self.level = None;
+    }
+    /// Draws the chunks of every layer that are visible from `camera`, which has to be the
+    /// current camera.
+    pub fn draw(
+        &mut self,
+        assets: &Assets,
+        level: usize,
+        world_state: &WorldState,
+        camera: &Camera2D,
+        time: f32,
+    ) {
+        let world = &assets.levels[level];
+        if self.level != Some(level) {
+            self.level = Some(level);
+            self.layers = world
+                .layers
+                .iter()
+                .map(|layer| LayerCache::new(&layer.tiles))
+                .collect();
+            self.tile_overrides = TileOverrides::default();
+        }
+        // chunks with tiles that changed, or changed back after a restart, are rendered again
+        let overrides = &world_state.tile_overrides;
+        if self.tile_overrides != *overrides {
+            let collision = &mut self.layers[world.collision_layer()];
+            for (x, y, _) in self
+                .tile_overrides
+                .iter()
+                .filter(|(x, y, gid)| overrides.get(*x, *y) != Some(*gid))
+                .chain(
+                    overrides
+                        .iter()
+                        .filter(|(x, y, gid)| self.tile_overrides.get(*x, *y) != Some(*gid)),
+                )
+            {
+                collision.invalidate(x, y);
+            }
+            self.tile_overrides = overrides.clone();
+        }
+
+        let time = (time * 1000.0) as u32;
+        let view = Rect::new(
+            camera.target.x - SCREEN_WIDTH / 2.0,
+            camera.target.y - SCREEN_HEIGHT / 2.0,
+            SCREEN_WIDTH,
+            SCREEN_HEIGHT,
+        );
+        // overrides only apply to the collision layer
+        let no_overrides = TileOverrides::default();
+        for (index, (layer, cache)) in world.layers.iter().zip(self.layers.iter_mut()).enumerate() {
+            let overrides = if index == world.collision_layer() {
+                &self.tile_overrides
+            } else {
+                &no_overrides
+            };
+            let origin = layer.origin(camera.target, world.parallax_origin).floor();
+            let color = Color::new(1.0, 1.0, 1.0, layer.opacity);
+            // only the chunks overlapping the view, clamped so a far away camera is cheap
+            let chunk_at = |pos: f32, first: i32, count: i32| {
+                ((pos / CHUNK_SIZE).floor().max(first as f32 - 1.0) as i32).min(first + count)
+            };
+            let (left, right) = (
+                chunk_at(view.x - origin.x, cache.x, cache.width),
+                chunk_at(view.right() - origin.x, cache.x, cache.width),
+            );
+            let (top, bottom) = (
+                chunk_at(view.y - origin.y, cache.y, cache.height),
+                chunk_at(view.bottom() - origin.y, cache.y, cache.height),
+            );
+            for cy in top..=bottom {
+                for cx in left..=right {
+                    let Some(index) = cache.index(cx, cy) else {
+                        continue;
+                    };
+                    let chunk = cache.chunks[index].get_or_insert_with(|| {
+                        let chunk = render_chunk(assets, &layer.tiles, overrides, (cx, cy));
+                        set_camera(camera);
+                        chunk
+                    });
+                    let pos = origin + vec2(cx as f32, cy as f32) * CHUNK_SIZE;
+                    if let Some(target) = &chunk.target {
+                        draw_texture(&target.texture, pos.x, pos.y, color);
+                    }
+                    for (x, y, tile) in chunk.animated.iter() {
+                        assets.tileset.draw_gid(
+                            origin.x + *x as f32 * 8.0,
+                            origin.y + *y as f32 * 8.0,
+                            assets.tile_animations.frame(*tile, time),
+                            color,
+                        );
+                    }
+                }
+            }
+            // tiles placed outside the layer's chunks have nothing to be cached in
+            for (x, y, tile) in overrides.iter() {
+                if tile != 0
+                    && cache
+                        .index(
+                            (x as i32).div_euclid(CHUNK_TILES),
+                            (y as i32).div_euclid(CHUNK_TILES),
+                        )
+                        .is_none()
+                {
+                    assets.tileset.draw_gid(
+                        origin.x + x as f32 * 8.0,
+                        origin.y + y as f32 * 8.0,
+                        assets.tile_animations.frame(tile, time),
+                        color,
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Renders the tiles of a chunk that don't animate into a texture. This changes the camera.
+fn render_chunk(
+    assets: &Assets,
+    tiles: &TileGrid,
+    overrides: &TileOverrides,
+    (cx, cy): (i32, i32),
+) -> CachedChunk {
+    let mut target = None;
+    let mut animated = Vec::new();
+    for y in 0..CHUNK_TILES {
+        for x in 0..CHUNK_TILES {
+            let (x, y) = ((cx * CHUNK_TILES + x) as i16, (cy * CHUNK_TILES + y) as i16);
+            let tile = overrides.tile(tiles, x, y);
+            if tile == 0 {
+                continue;
+            }
+            if assets.tile_animations.is_animated(tile) {
+                animated.push((x, y, tile));
+                continue;
+            }
+            if target.is_none() {
+                let new = render_target(CHUNK_SIZE as u32, CHUNK_SIZE as u32);
+                new.texture.set_filter(FilterMode::Nearest);
+                set_camera(&Camera2D {
+                    target: Vec2::splat(CHUNK_SIZE / 2.0),
+                    zoom: Vec2::splat(2.0 / CHUNK_SIZE),
+                    render_target: Some(new.clone()),
+                    ..Default::default()
+                });
+                clear_background(BLANK);
+                target = Some(new);
+            }
+            assets.tileset.draw_gid(
+                (x as i32 - cx * CHUNK_TILES) as f32 * 8.0,
+                (y as i32 - cy * CHUNK_TILES) as f32 * 8.0,
+                tile,
+                WHITE,
+            );
+        }
+    }
+    CachedChunk { target, animated }
+}
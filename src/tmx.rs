@@ -0,0 +1,1020 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, Read},
+    str::FromStr,
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use roxmltree::{Document, Node};
+use ruzstd::decoding::StreamingDecoder;
+
+/// An error encountered while loading a Tiled file, with enough context to find the problem.
+#[derive(Debug)]
+pub struct TmxError {
+    pub file: String,
+    pub layer: Option<String>,
+    pub line: u32,
+    pub kind: TmxErrorKind,
+}
+impl TmxError {
+    pub fn new(file: &str, layer: Option<&str>, line: u32, kind: TmxErrorKind) -> Self {
+        Self {
+            file: file.to_string(),
+            layer: layer.map(str::to_string),
+            line,
+            kind,
+        }
+    }
+}
+impl fmt::Display for TmxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: ", self.file, self.line)?;
+        if let Some(layer) = &self.layer {
+            write!(f, "layer \"{layer}\": ")?;
+        }
+        write!(f, "{}", self.kind)
+    }
+}
+impl std::error::Error for TmxError {}
+
+#[derive(Debug)]
+pub enum TmxErrorKind {
+    Xml(roxmltree::Error),
+    UnexpectedElement(String),
+    MissingLayer,
+    MissingAttribute(&'static str),
+    InvalidAttribute(&'static str, String),
+    InvalidTile(String),
+    WrongTileCount { expected: usize, found: usize },
+    TileOutOfRange(i32, i32),
+    UnsupportedEncoding(String),
+    UnsupportedCompression(String),
+    InvalidData(String),
+    UnsupportedMap(String),
+    InvalidProperty(String, String),
+    UnknownObject(String),
+    UnknownEnemy(String),
+}
+impl fmt::Display for TmxErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TmxErrorKind::Xml(err) => write!(f, "malformed xml: {err}"),
+            TmxErrorKind::UnexpectedElement(name) => write!(f, "unexpected <{name}> element"),
+            TmxErrorKind::MissingLayer => write!(f, "layer is missing"),
+            TmxErrorKind::MissingAttribute(name) => write!(f, "missing attribute \"{name}\""),
+            TmxErrorKind::InvalidAttribute(name, value) => {
+                write!(f, "invalid value \"{value}\" for attribute \"{name}\"")
+            }
+            TmxErrorKind::InvalidTile(value) => write!(f, "invalid tile \"{value}\""),
+            TmxErrorKind::WrongTileCount { expected, found } => {
+                write!(f, "expected {expected} tiles, found {found}")
+            }
+            TmxErrorKind::TileOutOfRange(x, y) => {
+                write!(f, "tile at {x}, {y} is too far from the origin")
+            }
+            TmxErrorKind::UnsupportedEncoding(encoding) => {
+                write!(f, "unsupported encoding \"{encoding}\"")
+            }
+            TmxErrorKind::UnsupportedCompression(compression) => {
+                write!(f, "unsupported compression \"{compression}\"")
+            }
+            TmxErrorKind::InvalidData(reason) => write!(f, "invalid tile data: {reason}"),
+            TmxErrorKind::UnsupportedMap(reason) => write!(f, "unsupported map: {reason}"),
+            TmxErrorKind::InvalidProperty(name, value) => {
+                write!(f, "invalid value \"{value}\" for property \"{name}\"")
+            }
+            TmxErrorKind::UnknownObject(ty) => write!(f, "unknown object type \"{ty}\""),
+            TmxErrorKind::UnknownEnemy(kind) => write!(f, "unknown enemy kind \"{kind}\""),
+        }
+    }
+}
+
+pub const FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+pub const FLIPPED_VERTICALLY: u32 = 0x4000_0000;
+pub const FLIPPED_DIAGONALLY: u32 = 0x2000_0000;
+/// All flag bits Tiled stores in a gid, including the one only used by hexagonal maps.
+const FLAGS: u32 = FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY | FLIPPED_DIAGONALLY | 0x1000_0000;
+
+/// Strips the flip flags from a gid.
+pub fn tile_id(gid: u32) -> u32 {
+    gid & !FLAGS
+}
+
+/// A parsed `.tmx` map. Tiles are kept as the raw gids found in the file.
+pub struct Map {
+    pub file: String,
+    pub line: u32,
+    /// Infinite maps store their layers in chunks, fixed size maps as a single chunk per layer
+    pub infinite: bool,
+    pub layers: Vec<TileLayer>,
+    pub object_layers: Vec<ObjectLayer>,
+    pub properties: Properties,
+    /// Position the camera has to be centered on for parallax layers to line up with the playfield
+    pub parallax_origin: (f32, f32),
+}
+impl Map {
+    pub fn layer(&self, name: &str) -> Option<&TileLayer> {
+        self.layers.iter().find(|layer| layer.name == name)
+    }
+    pub fn required_layer(&self, name: &str) -> Result<&TileLayer, TmxError> {
+        self.layer(name).ok_or_else(|| {
+            TmxError::new(
+                &self.file,
+                Some(name),
+                self.line,
+                TmxErrorKind::MissingLayer,
+            )
+        })
+    }
+}
+
+pub struct TileLayer {
+    pub name: String,
+    pub chunks: Vec<LayerChunk>,
+    /// How much the layer moves with the camera, where 1 moves with the playfield
+    pub parallax: (f32, f32),
+    /// Offset in pixels
+    pub offset: (f32, f32),
+    pub opacity: f32,
+}
+
+/// Drawing settings of a layer, which groups pass on to the layers inside them.
+#[derive(Clone, Copy)]
+struct LayerStyle {
+    parallax: (f32, f32),
+    offset: (f32, f32),
+    opacity: f32,
+    /// Hidden tile layers are left out of the map, so they are neither drawn nor collided with
+    visible: bool,
+}
+impl Default for LayerStyle {
+    fn default() -> Self {
+        Self {
+            parallax: (1.0, 1.0),
+            offset: (0.0, 0.0),
+            opacity: 1.0,
+            visible: true,
+        }
+    }
+}
+
+pub struct LayerChunk {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub line: u32,
+    pub tiles: Vec<u32>,
+}
+
+pub struct ObjectLayer {
+    pub name: String,
+    pub objects: Vec<Object>,
+}
+
+pub struct Object {
+    /// The object's class, called type in older versions of Tiled.
+    pub ty: String,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    /// Set for tile objects, which are positioned by their bottom left corner.
+    pub gid: Option<u32>,
+    pub line: u32,
+    pub properties: Properties,
+}
+
+/// Custom properties of a Tiled element, as their unparsed values.
+#[derive(Default)]
+pub struct Properties(HashMap<String, String>);
+impl Properties {
+    pub fn get<T: FromStr>(&self, name: &str) -> Result<Option<T>, TmxErrorKind> {
+        let Some(value) = self.0.get(name) else {
+            return Ok(None);
+        };
+        value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| TmxErrorKind::InvalidProperty(name.to_string(), value.clone()))
+    }
+}
+
+/// A parsed `.tsx` tileset.
+pub struct Tileset {
+    pub file: String,
+    pub tile_count: u32,
+    pub tiles: Vec<TileData>,
+}
+
+/// A `<tile>` element of a tileset, which only exists for tiles with extra data.
+pub struct TileData {
+    pub id: u32,
+    pub line: u32,
+    pub properties: Properties,
+    /// Empty for tiles that aren't animated
+    pub animation: Vec<Frame>,
+}
+
+/// A frame of an animated tile.
+pub struct Frame {
+    pub tile_id: u32,
+    /// Duration in milliseconds
+    pub duration: u32,
+}
+
+/// Keeps track of where in a document we are, so errors can point at it.
+struct Context<'a, 'input> {
+    file: &'a str,
+    doc: &'a Document<'input>,
+    layer: Option<&'a str>,
+}
+impl Context<'_, '_> {
+    fn line(&self, node: Node) -> u32 {
+        self.doc.text_pos_at(node.range().start).row
+    }
+    fn error(&self, node: Node, kind: TmxErrorKind) -> TmxError {
+        TmxError::new(self.file, self.layer, self.line(node), kind)
+    }
+    fn optional_attribute<T: FromStr>(
+        &self,
+        node: Node,
+        name: &'static str,
+    ) -> Result<Option<T>, TmxError> {
+        let Some(value) = node.attribute(name) else {
+            return Ok(None);
+        };
+        value.trim().parse().map(Some).map_err(|_| {
+            self.error(
+                node,
+                TmxErrorKind::InvalidAttribute(name, value.to_string()),
+            )
+        })
+    }
+    fn attribute<T: FromStr>(&self, node: Node, name: &'static str) -> Result<T, TmxError> {
+        self.optional_attribute(node, name)?
+            .ok_or_else(|| self.error(node, TmxErrorKind::MissingAttribute(name)))
+    }
+}
+
+pub fn parse_map(file: &str, xml: &str) -> Result<Map, TmxError> {
+    let doc = Document::parse(xml)
+        .map_err(|err| TmxError::new(file, None, err.pos().row, TmxErrorKind::Xml(err)))?;
+    let mut cx = Context {
+        file,
+        doc: &doc,
+        layer: None,
+    };
+    let root = doc.root_element();
+    if !root.has_tag_name("map") {
+        return Err(cx.error(
+            root,
+            TmxErrorKind::UnexpectedElement(root.tag_name().name().to_string()),
+        ));
+    }
+    if let Some(orientation) = root.attribute("orientation")
+        && orientation != "orthogonal"
+    {
+        return Err(cx.error(
+            root,
+            TmxErrorKind::UnsupportedMap(format!("{orientation} maps can't be loaded")),
+        ));
+    }
+    let infinite = cx.optional_attribute::<u8>(root, "infinite")?.unwrap_or(0) == 1;
+    // gids are read as ids of the game's tileset, which only works for a single tileset at 1
+    for (index, tileset) in root
+        .children()
+        .filter(|n| n.has_tag_name("tileset"))
+        .enumerate()
+    {
+        if index > 0 {
+            return Err(cx.error(
+                tileset,
+                TmxErrorKind::UnsupportedMap("only one tileset can be used".to_string()),
+            ));
+        }
+        let firstgid: u32 = cx.attribute(tileset, "firstgid")?;
+        if firstgid != 1 {
+            return Err(cx.error(
+                tileset,
+                TmxErrorKind::UnsupportedMap(format!(
+                    "the tileset has to start at gid 1, not {firstgid}"
+                )),
+            ));
+        }
+    }
+
+    let mut layers = Vec::new();
+    let mut object_layers = Vec::new();
+    parse_layers(
+        &mut cx,
+        root,
+        infinite,
+        LayerStyle::default(),
+        &mut layers,
+        &mut object_layers,
+    )?;
+
+    Ok(Map {
+        file: file.to_string(),
+        line: cx.line(root),
+        infinite,
+        layers,
+        object_layers,
+        properties: parse_properties(&cx, root)?,
+        parallax_origin: (
+            cx.optional_attribute(root, "parallaxoriginx")?
+                .unwrap_or(0.0),
+            cx.optional_attribute(root, "parallaxoriginy")?
+                .unwrap_or(0.0),
+        ),
+    })
+}
+
+/// Parses the layers inside a map or group, in the order they are drawn.
+fn parse_layers<'a, 'input>(
+    cx: &mut Context<'a, 'input>,
+    parent: Node<'a, 'input>,
+    infinite: bool,
+    parent_style: LayerStyle,
+    layers: &mut Vec<TileLayer>,
+    object_layers: &mut Vec<ObjectLayer>,
+) -> Result<(), TmxError> {
+    for node in parent.children().filter(|n| n.is_element()) {
+        if !["layer", "objectgroup", "group"].contains(&node.tag_name().name()) {
+            continue;
+        }
+        let name = node
+            .attribute("name")
+            .ok_or_else(|| cx.error(node, TmxErrorKind::MissingAttribute("name")))?;
+        cx.layer = Some(name);
+        // groups combine their settings with the ones of the layers inside them
+        let style = LayerStyle {
+            parallax: (
+                parent_style.parallax.0 * cx.optional_attribute(node, "parallaxx")?.unwrap_or(1.0),
+                parent_style.parallax.1 * cx.optional_attribute(node, "parallaxy")?.unwrap_or(1.0),
+            ),
+            offset: (
+                parent_style.offset.0 + cx.optional_attribute(node, "offsetx")?.unwrap_or(0.0),
+                parent_style.offset.1 + cx.optional_attribute(node, "offsety")?.unwrap_or(0.0),
+            ),
+            opacity: parent_style.opacity * cx.optional_attribute(node, "opacity")?.unwrap_or(1.0),
+            visible: parent_style.visible
+                && cx.optional_attribute::<u8>(node, "visible")?.unwrap_or(1) != 0,
+        };
+        match node.tag_name().name() {
+            "group" => parse_layers(cx, node, infinite, style, layers, object_layers)?,
+            "objectgroup" => {
+                let objects = node
+                    .children()
+                    .filter(|n| n.has_tag_name("object"))
+                    .map(|object| parse_object(cx, object))
+                    .collect::<Result<_, _>>()?;
+                object_layers.push(ObjectLayer {
+                    name: name.to_string(),
+                    objects,
+                });
+            }
+            _ if !style.visible => {}
+            _ => {
+                let mut chunks = Vec::new();
+                if let Some(data) = node.children().find(|n| n.has_tag_name("data")) {
+                    let encoding = Encoding::from_data(cx, data)?;
+                    if infinite {
+                        for chunk in data.children().filter(|n| n.has_tag_name("chunk")) {
+                            chunks.push(parse_chunk(cx, chunk, encoding)?);
+                        }
+                    } else {
+                        // fixed size maps store the whole layer as one block of tiles
+                        let (width, height) =
+                            (cx.attribute(node, "width")?, cx.attribute(node, "height")?);
+                        chunks.push(parse_tiles(cx, data, encoding, (0, 0), (width, height))?);
+                    }
+                }
+                layers.push(TileLayer {
+                    name: name.to_string(),
+                    chunks,
+                    parallax: style.parallax,
+                    offset: style.offset,
+                    opacity: style.opacity,
+                });
+            }
+        }
+        cx.layer = None;
+    }
+    Ok(())
+}
+
+pub fn parse_tileset(file: &str, xml: &str) -> Result<Tileset, TmxError> {
+    let doc = Document::parse(xml)
+        .map_err(|err| TmxError::new(file, None, err.pos().row, TmxErrorKind::Xml(err)))?;
+    let cx = Context {
+        file,
+        doc: &doc,
+        layer: None,
+    };
+    let root = doc.root_element();
+    if !root.has_tag_name("tileset") {
+        return Err(cx.error(
+            root,
+            TmxErrorKind::UnexpectedElement(root.tag_name().name().to_string()),
+        ));
+    }
+    let mut tiles = Vec::new();
+    for node in root.children().filter(|n| n.has_tag_name("tile")) {
+        let mut animation = Vec::new();
+        if let Some(frames) = node.children().find(|n| n.has_tag_name("animation")) {
+            for frame in frames.children().filter(|n| n.has_tag_name("frame")) {
+                animation.push(Frame {
+                    tile_id: cx.attribute(frame, "tileid")?,
+                    duration: cx.attribute(frame, "duration")?,
+                });
+            }
+        }
+        tiles.push(TileData {
+            id: cx.attribute(node, "id")?,
+            line: cx.line(node),
+            properties: parse_properties(&cx, node)?,
+            animation,
+        });
+    }
+    Ok(Tileset {
+        file: file.to_string(),
+        tile_count: cx.attribute(root, "tilecount")?,
+        tiles,
+    })
+}
+
+fn parse_object(cx: &Context, node: Node) -> Result<Object, TmxError> {
+    Ok(Object {
+        ty: node
+            .attribute("type")
+            .or(node.attribute("class"))
+            .unwrap_or_default()
+            .to_string(),
+        x: cx.attribute(node, "x")?,
+        y: cx.attribute(node, "y")?,
+        width: cx.optional_attribute(node, "width")?.unwrap_or(0.0),
+        height: cx.optional_attribute(node, "height")?.unwrap_or(0.0),
+        gid: cx.optional_attribute(node, "gid")?,
+        line: cx.line(node),
+        properties: parse_properties(cx, node)?,
+    })
+}
+
+fn parse_properties(cx: &Context, node: Node) -> Result<Properties, TmxError> {
+    let mut properties = Properties::default();
+    let Some(list) = node.children().find(|n| n.has_tag_name("properties")) else {
+        return Ok(properties);
+    };
+    for property in list.children().filter(|n| n.has_tag_name("property")) {
+        let name: String = cx.attribute(property, "name")?;
+        // multiline strings are stored as text instead of in the value attribute
+        let value = property
+            .attribute("value")
+            .or(property.text())
+            .unwrap_or_default();
+        properties.0.insert(name, value.to_string());
+    }
+    Ok(properties)
+}
+
+/// How the tiles inside a `<data>` element are stored.
+#[derive(Clone, Copy)]
+enum Encoding {
+    Csv,
+    Base64(Compression),
+}
+#[derive(Clone, Copy)]
+enum Compression {
+    None,
+    Zlib,
+    Gzip,
+    Zstd,
+}
+impl Encoding {
+    fn from_data(cx: &Context, data: Node) -> Result<Self, TmxError> {
+        let compression = match data.attribute("compression") {
+            None => Compression::None,
+            Some("zlib") => Compression::Zlib,
+            Some("gzip") => Compression::Gzip,
+            Some("zstd") => Compression::Zstd,
+            Some(other) => {
+                return Err(cx.error(
+                    data,
+                    TmxErrorKind::UnsupportedCompression(other.to_string()),
+                ));
+            }
+        };
+        match (data.attribute("encoding"), compression) {
+            (Some("csv"), Compression::None) => Ok(Encoding::Csv),
+            (Some("base64"), compression) => Ok(Encoding::Base64(compression)),
+            (Some("csv"), _) => Err(cx.error(
+                data,
+                TmxErrorKind::UnsupportedCompression(
+                    data.attribute("compression").unwrap().to_string(),
+                ),
+            )),
+            (encoding, _) => Err(cx.error(
+                data,
+                TmxErrorKind::UnsupportedEncoding(encoding.unwrap_or("xml").to_string()),
+            )),
+        }
+    }
+}
+
+fn parse_chunk(cx: &Context, node: Node, encoding: Encoding) -> Result<LayerChunk, TmxError> {
+    let pos = (cx.attribute(node, "x")?, cx.attribute(node, "y")?);
+    let size = (cx.attribute(node, "width")?, cx.attribute(node, "height")?);
+    parse_tiles(cx, node, encoding, pos, size)
+}
+
+/// Reads the tiles of a `<chunk>`, or of a `<data>` element of a fixed size map.
+fn parse_tiles(
+    cx: &Context,
+    node: Node,
+    encoding: Encoding,
+    (x, y): (i32, i32),
+    (width, height): (u32, u32),
+) -> Result<LayerChunk, TmxError> {
+    let tiles = match encoding {
+        Encoding::Csv => parse_csv(cx, node)?,
+        Encoding::Base64(compression) => parse_base64(cx, node, compression)?,
+    };
+    let expected = (width * height) as usize;
+    if tiles.len() != expected {
+        return Err(cx.error(
+            node,
+            TmxErrorKind::WrongTileCount {
+                expected,
+                found: tiles.len(),
+            },
+        ));
+    }
+    Ok(LayerChunk {
+        x,
+        y,
+        width,
+        line: cx.line(node),
+        tiles,
+    })
+}
+
+fn parse_csv(cx: &Context, node: Node) -> Result<Vec<u32>, TmxError> {
+    let Some(text) = node.children().find(|n| n.is_text()) else {
+        return Ok(Vec::new());
+    };
+    let mut tiles = Vec::new();
+    let rows = text.text().unwrap_or_default().split('\n');
+    for (line, row) in (cx.line(text)..).zip(rows) {
+        for item in row
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+        {
+            let tile = item.parse().map_err(|_| {
+                TmxError::new(
+                    cx.file,
+                    cx.layer,
+                    line,
+                    TmxErrorKind::InvalidTile(item.to_string()),
+                )
+            })?;
+            tiles.push(tile);
+        }
+    }
+    Ok(tiles)
+}
+
+fn parse_base64(cx: &Context, node: Node, compression: Compression) -> Result<Vec<u32>, TmxError> {
+    let invalid =
+        |err: &dyn fmt::Display| cx.error(node, TmxErrorKind::InvalidData(err.to_string()));
+    let text = node.text().unwrap_or_default().trim();
+    let bytes = STANDARD.decode(text).map_err(|err| invalid(&err))?;
+    let bytes = match compression {
+        Compression::None => bytes,
+        Compression::Zlib => read_all(ZlibDecoder::new(&bytes[..])).map_err(|err| invalid(&err))?,
+        Compression::Gzip => read_all(GzDecoder::new(&bytes[..])).map_err(|err| invalid(&err))?,
+        Compression::Zstd => {
+            let decoder = StreamingDecoder::new(&bytes[..]).map_err(|err| invalid(&err))?;
+            read_all(decoder).map_err(|err| invalid(&err))?
+        }
+    };
+    if bytes.len() % 4 != 0 {
+        return Err(invalid(&format!(
+            "{} bytes is not a whole number of tiles",
+            bytes.len()
+        )));
+    }
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|gid| u32::from_le_bytes(gid.try_into().unwrap()))
+        .collect())
+}
+
+fn read_all(mut reader: impl Read) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+// the binary format levels are embedded in the game as, written by build.rs.
+// numbers are stored as LEB128 varints, and runs of the same tile are stored once.
+
+/// Changed whenever the binary format changes, so stale data fails to load instead of misloading.
+const BINARY_MAGIC: &[u8; 4] = b"GNB2";
+
+/// Encodes maps in the compact binary format, keeping their line numbers for errors.
+// only used by build.rs
+#[allow(dead_code)]
+pub fn encode_maps(maps: &[Map]) -> Vec<u8> {
+    let mut w = Writer(BINARY_MAGIC.to_vec());
+    w.u32(maps.len() as u32);
+    for map in maps.iter() {
+        w.str(&map.file);
+        w.u32(map.line);
+        w.u32(map.infinite as u32);
+        w.f32(map.parallax_origin.0);
+        w.f32(map.parallax_origin.1);
+        w.properties(&map.properties);
+        w.u32(map.layers.len() as u32);
+        for layer in map.layers.iter() {
+            w.str(&layer.name);
+            for value in [
+                layer.parallax.0,
+                layer.parallax.1,
+                layer.offset.0,
+                layer.offset.1,
+                layer.opacity,
+            ] {
+                w.f32(value);
+            }
+            w.u32(layer.chunks.len() as u32);
+            for chunk in layer.chunks.iter() {
+                w.i32(chunk.x);
+                w.i32(chunk.y);
+                w.u32(chunk.width);
+                w.u32(chunk.line);
+                w.u32(chunk.tiles.len() as u32);
+                let mut tiles = chunk.tiles.iter().peekable();
+                while let Some(tile) = tiles.next() {
+                    let mut run = 1;
+                    while tiles.next_if_eq(&tile).is_some() {
+                        run += 1;
+                    }
+                    w.u32(run);
+                    w.u32(*tile);
+                }
+            }
+        }
+        w.u32(map.object_layers.len() as u32);
+        for layer in map.object_layers.iter() {
+            w.str(&layer.name);
+            w.u32(layer.objects.len() as u32);
+            for object in layer.objects.iter() {
+                w.str(&object.ty);
+                for value in [object.x, object.y, object.width, object.height] {
+                    w.f32(value);
+                }
+                // gids of tile objects are never 0, so 0 means the object isn't one
+                w.u32(object.gid.unwrap_or(0));
+                w.u32(object.line);
+                w.properties(&object.properties);
+            }
+        }
+    }
+    w.0
+}
+
+/// Decodes maps written by [`encode_maps`].
+pub fn decode_maps(file: &str, bytes: &[u8]) -> Result<Vec<Map>, TmxError> {
+    let mut r = Reader { bytes, pos: 0 };
+    decode(&mut r).map_err(|kind| TmxError::new(file, None, 0, kind))
+}
+
+fn decode(r: &mut Reader) -> Result<Vec<Map>, TmxErrorKind> {
+    if r.take(BINARY_MAGIC.len())? != BINARY_MAGIC {
+        return Err(TmxErrorKind::InvalidData(
+            "not level data, or from another version of the game".to_string(),
+        ));
+    }
+    let mut maps = Vec::new();
+    for _ in 0..r.u32()? {
+        let file = r.str()?;
+        let line = r.u32()?;
+        let infinite = r.u32()? != 0;
+        let parallax_origin = (r.f32()?, r.f32()?);
+        let properties = r.properties()?;
+        let mut layers = Vec::new();
+        for _ in 0..r.u32()? {
+            let name = r.str()?;
+            let (parallax, offset, opacity) =
+                ((r.f32()?, r.f32()?), (r.f32()?, r.f32()?), r.f32()?);
+            let mut chunks = Vec::new();
+            for _ in 0..r.u32()? {
+                let (x, y, width, line) = (r.i32()?, r.i32()?, r.u32()?, r.u32()?);
+                let count = r.u32()? as usize;
+                let mut tiles = Vec::with_capacity(count);
+                while tiles.len() < count {
+                    let (run, tile) = (r.u32()? as usize, r.u32()?);
+                    if run == 0 || tiles.len() + run > count {
+                        return Err(TmxErrorKind::InvalidData("bad run of tiles".to_string()));
+                    }
+                    tiles.resize(tiles.len() + run, tile);
+                }
+                chunks.push(LayerChunk {
+                    x,
+                    y,
+                    width,
+                    line,
+                    tiles,
+                });
+            }
+            layers.push(TileLayer {
+                name,
+                chunks,
+                parallax,
+                offset,
+                opacity,
+            });
+        }
+        let mut object_layers = Vec::new();
+        for _ in 0..r.u32()? {
+            let name = r.str()?;
+            let mut objects = Vec::new();
+            for _ in 0..r.u32()? {
+                objects.push(Object {
+                    ty: r.str()?,
+                    x: r.f32()?,
+                    y: r.f32()?,
+                    width: r.f32()?,
+                    height: r.f32()?,
+                    gid: Some(r.u32()?).filter(|gid| *gid != 0),
+                    line: r.u32()?,
+                    properties: r.properties()?,
+                });
+            }
+            object_layers.push(ObjectLayer { name, objects });
+        }
+        maps.push(Map {
+            file,
+            line,
+            infinite,
+            layers,
+            object_layers,
+            properties,
+            parallax_origin,
+        });
+    }
+    if r.pos != r.bytes.len() {
+        return Err(TmxErrorKind::InvalidData("trailing data".to_string()));
+    }
+    Ok(maps)
+}
+
+#[allow(dead_code)]
+struct Writer(Vec<u8>);
+#[allow(dead_code)]
+impl Writer {
+    fn u32(&mut self, mut value: u32) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+    fn i32(&mut self, value: i32) {
+        // zigzag encoding keeps small negative numbers small
+        self.u32(((value << 1) ^ (value >> 31)) as u32);
+    }
+    fn f32(&mut self, value: f32) {
+        self.0.extend(value.to_le_bytes());
+    }
+    fn str(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.0.extend(value.as_bytes());
+    }
+    fn properties(&mut self, properties: &Properties) {
+        // sorted, so building the same levels always gives the same bytes
+        let mut properties: Vec<_> = properties.0.iter().collect();
+        properties.sort();
+        self.u32(properties.len() as u32);
+        for (name, value) in properties {
+            self.str(name);
+            self.str(value);
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+impl Reader<'_> {
+    fn take(&mut self, count: usize) -> Result<&[u8], TmxErrorKind> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos + count)
+            .ok_or_else(|| TmxErrorKind::InvalidData("unexpected end of data".to_string()))?;
+        self.pos += count;
+        Ok(bytes)
+    }
+    fn u32(&mut self) -> Result<u32, TmxErrorKind> {
+        let mut value = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= ((byte & 0x7f) as u32) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(TmxErrorKind::InvalidData("number too large".to_string()))
+    }
+    fn i32(&mut self) -> Result<i32, TmxErrorKind> {
+        let value = self.u32()?;
+        Ok((value >> 1) as i32 ^ -((value & 1) as i32))
+    }
+    fn f32(&mut self) -> Result<f32, TmxErrorKind> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    fn str(&mut self) -> Result<String, TmxErrorKind> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| TmxErrorKind::InvalidData("invalid utf-8".to_string()))
+    }
+    fn properties(&mut self) -> Result<Properties, TmxErrorKind> {
+        let mut properties = Properties::default();
+        for _ in 0..self.u32()? {
+            let name = self.str()?;
+            properties.0.insert(name, self.str()?);
+        }
+        Ok(properties)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a fixed size 2x2 map, with `layers` inside the `<map>` element from line 2 on
+    fn map(layers: &str) -> Result<Map, TmxError> {
+        parse_map(
+            "test.tmx",
+            &format!(
+                "<map width=\"2\" height=\"2\" tilewidth=\"8\" tileheight=\"8\" infinite=\"0\">\n\
+                 {layers}\n\
+                 </map>"
+            ),
+        )
+    }
+
+    fn error<T>(result: Result<T, TmxError>) -> String {
+        match result {
+            Ok(_) => panic!("expected an error"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn tilesets_not_starting_at_1_are_errors() {
+        let result = map(r#"<tileset firstgid="5" source="tileset.tsx"/>"#);
+        assert_eq!(
+            error(result),
+            "test.tmx:2: unsupported map: the tileset has to start at gid 1, not 5"
+        );
+    }
+
+    #[test]
+    fn second_tilesets_are_errors() {
+        let result = map(concat!(
+            "<tileset firstgid=\"1\" source=\"tileset.tsx\"/>\n",
+            "<tileset firstgid=\"129\" source=\"other.tsx\"/>",
+        ));
+        assert_eq!(
+            error(result),
+            "test.tmx:3: unsupported map: only one tileset can be used"
+        );
+    }
+
+    #[test]
+    fn hidden_layers_are_skipped() {
+        let map = map(concat!(
+            "<layer name=\"collision\" width=\"1\" height=\"1\"><data encoding=\"csv\">50</data></layer>\n",
+            "<layer name=\"notes\" visible=\"0\" width=\"1\" height=\"1\"><data encoding=\"csv\">1</data></layer>\n",
+            "<group name=\"scenery\" visible=\"0\">\n",
+            "<layer name=\"trees\" width=\"1\" height=\"1\"><data encoding=\"csv\">1</data></layer>\n",
+            "</group>",
+        ))
+        .unwrap();
+        let names: Vec<_> = map.layers.iter().map(|layer| layer.name.as_str()).collect();
+        assert_eq!(names, ["collision"]);
+    }
+
+    #[test]
+    fn missing_collision_layer_names_the_file_and_layer() {
+        let map = map(r#"<layer name="decoration" width="2" height="2"/>"#).unwrap();
+        assert_eq!(
+            error(map.required_layer("collision")),
+            "test.tmx:1: layer \"collision\": layer is missing"
+        );
+    }
+
+    #[test]
+    fn invalid_csv_tile_names_its_line() {
+        let result = map(concat!(
+            "<layer name=\"collision\" width=\"2\" height=\"2\">\n",
+            "<data encoding=\"csv\">\n",
+            "1,2,\n",
+            "3,x\n",
+            "</data>\n",
+            "</layer>",
+        ));
+        assert_eq!(
+            error(result),
+            "test.tmx:5: layer \"collision\": invalid tile \"x\""
+        );
+    }
+
+    #[test]
+    fn wrong_tile_count_names_the_data() {
+        let result = map(concat!(
+            "<layer name=\"collision\" width=\"2\" height=\"2\">\n",
+            "<data encoding=\"csv\">1,2,3</data>\n",
+            "</layer>",
+        ));
+        assert_eq!(
+            error(result),
+            "test.tmx:3: layer \"collision\": expected 4 tiles, found 3"
+        );
+    }
+
+    /// Parses a collision layer whose `<data>` element, on line 3, has `attributes` and `text`
+    fn data(attributes: &str, text: &str) -> Result<Vec<u32>, TmxError> {
+        let map = map(&format!(
+            "<layer name=\"collision\" width=\"2\" height=\"2\">\n\
+             <data {attributes}>{text}</data>\n\
+             </layer>"
+        ))?;
+        Ok(map.layers[0].chunks[0].tiles.clone())
+    }
+
+    #[test]
+    fn every_encoding_decodes_the_same_tiles() {
+        // the second tile is flipped horizontally
+        let tiles = vec![1, FLIPPED_HORIZONTALLY | 50, 33, 0];
+        assert_eq!(
+            data(r#"encoding="csv""#, "1,2147483698,\n33,0").unwrap(),
+            tiles
+        );
+        for (compression, text) in [
+            ("", "AQAAADIAAIAhAAAAAAAAAA=="),
+            (r#"compression="zlib""#, "eJxjZGBgMGJgaFBkgAAACAAA1Q=="),
+            (
+                r#"compression="gzip""#,
+                "H4sIAAAAAAACA2NkYGAwYmBoUGSAAADYEEjpEAAAAA==",
+            ),
+            (
+                r#"compression="zstd""#,
+                "KLUv/SQQgQAAAQAAADIAAIAhAAAAAAAAAPpYO2U=",
+            ),
+        ] {
+            let attributes = format!(r#"encoding="base64" {compression}"#);
+            // Tiled puts the data on its own line
+            assert_eq!(
+                data(&attributes, &format!("\n   {text}\n  ")).unwrap(),
+                tiles,
+                "{compression}"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_base64_is_an_error() {
+        let Err(err) = data(r#"encoding="base64""#, "not base64!") else {
+            panic!("expected an error");
+        };
+        assert!(matches!(err.kind, TmxErrorKind::InvalidData(_)), "{err}");
+        assert_eq!(err.line, 3);
+        assert_eq!(err.layer.as_deref(), Some("collision"));
+    }
+
+    #[test]
+    fn partial_tiles_are_an_error() {
+        assert_eq!(
+            error(data(r#"encoding="base64""#, "AQAAADIAAA==")),
+            "test.tmx:3: layer \"collision\": \
+             invalid tile data: 7 bytes is not a whole number of tiles"
+        );
+    }
+
+    #[test]
+    fn malformed_xml_names_its_line() {
+        let result = map("<layer name=\"collision\">\n</map>");
+        assert_eq!(
+            error(result),
+            "test.tmx:3: malformed xml: expected 'layer' tag, not 'map' at 3:1"
+        );
+    }
+}
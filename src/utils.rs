@@ -8,10 +8,20 @@ pub const GROUND_FRICTION: f32 = 0.17 * 60.0;
 pub const AIR_DRAG: f32 = 0.15 * 60.0;
 pub const GRAVITY: f32 = 0.17 * 3600.0;
 pub const ACCELERATION: f32 = 2400.0 / 2.0;
+/// Fastest bodies can rise or fall
+pub const MAX_VERTICAL_SPEED: f32 = 8.0 * 60.0;
+
+/// Length of a game step, in seconds. The game's speeds were tuned at 60 fps.
+pub const FIXED_TIMESTEP: f32 = 1.0 / 60.0;
+/// Longest frame the game catches up on. Longer frames slow the game down instead.
+pub const MAX_FRAME_TIME: f32 = 0.25;
 
 pub const BOAT_WAIT_TIME: f32 = 0.2;
 pub const BOAT_MOVE_SPEED: f32 = 64.0;
 
+/// Directory extra levels are loaded from on native builds, relative to the working directory
+pub const USER_LEVELS_DIR: &str = "user_levels";
+
 pub fn create_camera(w: f32, h: f32) -> Camera2D {
     let rt = render_target(w as u32, h as u32);
     rt.texture.set_filter(FilterMode::Nearest);